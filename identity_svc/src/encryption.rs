@@ -1,8 +1,12 @@
 //! Provides a function that creates a FUSE mount which mirrors encrypted files to $STORAGE_PATH
 
+use crate::error::{IdentityError, Result};
+use crate::key_provider::build_key_provider;
+use crate::sealing;
 use crate::storage::STORAGE_PATH;
+use crate::store::StateStore;
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
 use lazy_static::lazy_static;
-use sha2::{Digest, Sha256};
 use std::{fs, path::PathBuf};
 use tokio::process::Command as TokioCommand;
 
@@ -17,15 +21,24 @@ lazy_static! {
 
 /// Mounts a virtual storage directory in tmp that mirrors encrypted files to $STORAGE_PATH
 ///
+/// The encryption key is bound to the TD's current measurement in addition to the owner
+/// pubkey and PPID, and is refused if the measurement doesn't match the sealed policy, so
+/// a rolled-back or tampered workload image can't unseal data sealed under a newer one.
+///
 /// # Arguments
 ///
 /// * `owner_pubkey_bytes` - The owner's public key, unencoded
 /// * `ppid` - The encrypted PPID of this TDX instance
+/// * `store` - The state store the seal policy is persisted through
 ///
 /// # Panics
 ///
 /// Panics if the gocryptfs initialization or mount fails
-pub async fn initialize_encryption(owner_pubkey_bytes: &[u8], ppid: &[u8]) {
+pub async fn initialize_encryption(
+    owner_pubkey_bytes: &[u8],
+    ppid: &[u8],
+    store: &dyn StateStore,
+) -> Result<()> {
     // Create required directories if they don't exist
     // Ignore errors here, they can be unpredictable if the directory is already mounted
     let _ = fs::create_dir_all(&*MOUNT_PATH);
@@ -33,8 +46,17 @@ pub async fn initialize_encryption(owner_pubkey_bytes: &[u8], ppid: &[u8]) {
         fs::create_dir_all(&*ENCRYPTED_PATH).expect("Failed to create ENCRYPTED_PATH");
     }
 
-    // Generate and save encryption key
-    let key = generate_encryption_key(owner_pubkey_bytes, ppid);
+    // Check the current measurement against the sealed policy before deriving the key
+    let owner_pubkey_array: [u8; PUBLIC_KEY_LENGTH] = owner_pubkey_bytes
+        .try_into()
+        .map_err(|_| IdentityError::internal("Invalid owner public key length"))?;
+    let measurement = sealing::current_measurement().await?;
+    let policy = sealing::verify_seal_policy(store, &measurement, None, &owner_pubkey_array).await?;
+
+    // Derive and save encryption key
+    let key = build_key_provider()
+        .derive_key(owner_pubkey_bytes, ppid, &policy.measurement)
+        .await?;
     fs::write(&*KEY_PATH, &key).expect("Failed to write encryption key");
 
     // Initialize if not already initialized
@@ -45,6 +67,8 @@ pub async fn initialize_encryption(owner_pubkey_bytes: &[u8], ppid: &[u8]) {
     // Mount MOUNT_PATH
     unmount().await;
     mount().await;
+
+    Ok(())
 }
 
 /// Calls gocryptfs init on the encrypted storage directory
@@ -98,13 +122,3 @@ async fn unmount() {
         .status()
         .await;
 }
-
-/// Dummy function to generate an encryption key
-/// In a real implementation, this would interact with a KMS
-/// For now, we'll just hash the concatenation of owner pubkey and ppid
-fn generate_encryption_key(owner_pubkey: &[u8], ppid: &[u8]) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(owner_pubkey);
-    hasher.update(ppid);
-    hex::encode(hasher.finalize())
-}
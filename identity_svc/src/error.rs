@@ -21,6 +21,9 @@ pub enum IdentityError {
 
     #[error("Registry error: {0}")]
     Registry(String),
+
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
 }
 
 /// Helper methods for creating `IdentityError` variants
@@ -40,6 +43,10 @@ impl IdentityError {
     pub fn registry(e: impl ToString) -> Self {
         Self::Registry(e.to_string())
     }
+
+    pub fn too_many_requests(e: impl ToString) -> Self {
+        Self::TooManyRequests(e.to_string())
+    }
 }
 
 #[derive(Serialize)]
@@ -55,6 +62,7 @@ impl IntoResponse for IdentityError {
             Self::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
             Self::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             Self::Registry(msg) => (StatusCode::BAD_REQUEST, msg),
+            Self::TooManyRequests(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
         };
 
         (status, Json(ErrorResponse { error: message })).into_response()
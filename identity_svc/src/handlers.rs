@@ -3,34 +3,44 @@
 use crate::{
     encryption::initialize_encryption,
     error::{IdentityError, Result},
+    registry_client::verify_image_digest,
+    sealing::{self, SealPolicy},
     ssh::{start_ssh_server, stop_ssh_server},
     state::{AppState, WorkloadConfig},
-    validation::{validate_owner_token, validate_signature_header},
-    workload::run_container,
+    token::{mint_owner_token, validate_owner_token, CAPABILITY_GRANT_SUBJECT, OWNER_REGISTRATION_SUBJECT},
+    validation::validate_signature_header,
+    workload::{contains_dangerous_capability, CONTAINER_NAME},
 };
 use axum::{
     body::Bytes,
-    extract::State,
-    http::HeaderMap,
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, Method},
     routing::{get, post},
     Json, Router,
 };
-use ed25519_dalek::{Signer, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use ed25519_dalek::{PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
 use serde::Deserialize;
 use shared::{
     sig_validation::verify_instance_signature,
     types::{hex_serde, IdentityInfo, UserType},
 };
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 /// Creates the router for the identity service
 pub(crate) fn create_router(state: Arc<AppState>) -> Router {
     Router::new()
         .route("/instance/pubkey", get(get_instance_pubkey))
+        .route("/challenge", post(issue_challenge))
         .route("/operator/register", post(register_operator))
         .route("/owner/register", post(register_owner))
         .route("/workload/configure", post(configure_workload))
         .route("/workload/expose", post(expose_workload))
+        .route("/owner/token", post(mint_capability_token))
+        .route("/seal/migrate", post(migrate_seal))
+        .route("/workloads", get(list_workloads))
+        .route("/workloads/:name/start", post(start_workload))
+        .route("/workloads/:name/stop", post(stop_workload))
         .with_state(state)
 }
 
@@ -48,21 +58,50 @@ pub struct ConfigureWorkloadRequest {
     #[serde(with = "hex_serde")]
     pub instance_pubkey: [u8; PUBLIC_KEY_LENGTH],
     pub image: String,
+    /// The `sha256:...` digest the caller expects `image` to resolve to. Checked against
+    /// the registry's manifest before the image is pulled or trusted.
+    pub image_digest: String,
     pub persist_dirs: Vec<String>,
     pub port: u16,
+    #[serde(default)]
+    pub memory: Option<i64>,
+    #[serde(default)]
+    pub memory_swap: Option<i64>,
+    #[serde(default)]
+    pub cpus: Option<f64>,
+    #[serde(default)]
+    pub pids_limit: Option<i64>,
+    #[serde(default)]
+    pub read_only_rootfs: bool,
+    #[serde(default)]
+    pub cap_add: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ExposeWorkloadRequest {
     #[serde(with = "hex_serde")]
     pub instance_pubkey: [u8; PUBLIC_KEY_LENGTH],
-    pub image: String,
+    pub image_digest: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MigrateSealRequest {
+    #[serde(with = "hex_serde")]
+    pub instance_pubkey: [u8; PUBLIC_KEY_LENGTH],
+    pub version: u64,
+    /// The new measurement to seal under, matching `sealing::current_measurement`'s output
+    #[serde(with = "hex_serde")]
+    pub measurement: Vec<u8>,
+    /// The owner's signature over `version` and `measurement`, authorizing this migration
+    #[serde(with = "hex_serde")]
+    pub signature: [u8; SIGNATURE_LENGTH],
 }
 
 /// Configures the workload with the specified image and persist directories
 /// This will start a podman container and SSH server, but not expose the port
 async fn configure_workload(
     State(state): State<Arc<AppState>>,
+    method: Method,
     headers: HeaderMap,
     body: Bytes,
 ) -> Result<Json<serde_json::Value>> {
@@ -77,28 +116,60 @@ async fn configure_workload(
         return Err(IdentityError::unauthorized("Owner not registered"));
     };
 
-    // Verify that the signature header matches the POST body
-    validate_signature_header(&headers, payload_raw, owner.pubkey)?;
+    // Verify that the signature header matches the nonce-prefixed POST body
+    validate_signature_header(
+        &headers,
+        &method,
+        "/workload/configure",
+        payload_raw,
+        owner.pubkey,
+        &state.nonces,
+    )?;
 
     // Validate instance pubkey matches the stored instance pubkey
     if payload.instance_pubkey != state.instance_pubkey_bytes {
         return Err(IdentityError::unauthorized("Instance pubkey mismatch"));
     }
 
+    // Re-adding a dangerous capability requires the owner token on top of the owner's
+    // signature, since a leaked signed request shouldn't be enough to escalate it
+    if contains_dangerous_capability(&payload.cap_add) {
+        validate_owner_token(
+            &headers,
+            &state.instance_pubkey_bytes,
+            &state.used_jtis,
+            CAPABILITY_GRANT_SUBJECT,
+        )?;
+    }
+
+    // Resolve the image's manifest and check its digest matches the one the owner pinned,
+    // so the container that's about to run is exactly the bytes the owner expects
+    let image_digest = verify_image_digest(&payload.image, &payload.image_digest).await?;
+
     let workload_config = WorkloadConfig {
         image: payload.image,
+        image_digest,
         persist_dirs: payload.persist_dirs,
         port: payload.port,
         finalized: false,
+        memory: payload.memory,
+        memory_swap: payload.memory_swap,
+        cpus: payload.cpus,
+        pids_limit: payload.pids_limit,
+        read_only_rootfs: payload.read_only_rootfs,
+        cap_add: payload.cap_add,
     };
 
     // Save to state and disk
     state.configure_workload(&workload_config).await?;
 
     // Run the container with the specified config and start SSH server
-    run_container(&workload_config).await?;
+    state.workloads.start(CONTAINER_NAME, workload_config).await?;
     start_ssh_server(&owner.pubkey).await;
 
+    // Re-attest and re-register so the quote commits to the pinned image digest
+    state.register_with_registry().await?;
+
     Ok(Json(serde_json::json!({ "status": "success" })))
 }
 
@@ -106,6 +177,7 @@ async fn configure_workload(
 /// and rerunning the container with the port exposed
 async fn expose_workload(
     State(state): State<Arc<AppState>>,
+    method: Method,
     headers: HeaderMap,
     body: Bytes,
 ) -> Result<Json<serde_json::Value>> {
@@ -120,8 +192,15 @@ async fn expose_workload(
         return Err(IdentityError::unauthorized("Owner not registered"));
     };
 
-    // Verify that the signature header matches the POST body
-    validate_signature_header(&headers, payload_raw, owner.pubkey)?;
+    // Verify that the signature header matches the nonce-prefixed POST body
+    validate_signature_header(
+        &headers,
+        &method,
+        "/workload/expose",
+        payload_raw,
+        owner.pubkey,
+        &state.nonces,
+    )?;
 
     // Validate instance pubkey matches the stored instance pubkey
     if payload.instance_pubkey != state.instance_pubkey_bytes {
@@ -134,24 +213,235 @@ async fn expose_workload(
         return Err(IdentityError::invalid_request("Workload not configured"));
     };
 
-    // Verify instance pubkey matches stored config
-    if payload.image != workload_config.image {
+    // Verify the caller's pinned digest matches what was actually configured, rather than
+    // trusting a (mutable) tag match that could silently mean a different set of bytes
+    if payload.image_digest != workload_config.image_digest {
         return Err(IdentityError::unauthorized(
-            "Instance image mismatch with stored config",
+            "Instance image digest mismatch with stored config",
         ));
     }
 
     // Save to state and disk
-    state.finalize_workload()?;
+    state.finalize_workload().await?;
 
     // Stop SSH server and rerun container with port exposed
     stop_ssh_server().await;
-    run_container(&WorkloadConfig {
-        finalized: true,
-        ..workload_config
-    })
+    state
+        .workloads
+        .start(
+            CONTAINER_NAME,
+            WorkloadConfig {
+                finalized: true,
+                ..workload_config
+            },
+        )
+        .await?;
+
+    Ok(Json(serde_json::json!({ "status": "success" })))
+}
+
+/// Mints a fresh single-use owner token authorizing one dangerous-capability change, for the
+/// owner to present back via `x-token` to `/workload/configure` or `/workloads/:name/start`.
+/// Without this, the one-time token `register_operator` minted (already consumed by
+/// `register_owner` during onboarding) would be the only owner token that ever exists, and
+/// dangerous capabilities could never be re-added for the lifetime of the instance.
+async fn mint_capability_token(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>> {
+    // Verify owner has been registered
+    let owner = state.owner.read().unwrap().clone();
+    let Some(owner) = owner.as_ref() else {
+        return Err(IdentityError::unauthorized("Owner not registered"));
+    };
+
+    // There's no body to sign over, so the signature just covers the nonce itself
+    validate_signature_header(
+        &headers,
+        &method,
+        "/owner/token",
+        Vec::new(),
+        owner.pubkey,
+        &state.nonces,
+    )?;
+
+    let owner_token = mint_owner_token(
+        &*state.signer,
+        &state.instance_pubkey_bytes,
+        CAPABILITY_GRANT_SUBJECT,
+    )
     .await?;
 
+    Ok(Json(serde_json::json!({
+        "status": "success",
+        "owner_token": owner_token,
+    })))
+}
+
+/// Lets the owner authorize moving the sealed volume onto a new TD measurement (e.g. after
+/// upgrading the workload image) by presenting a version and measurement signed with the
+/// owner's key. Without this, any legitimate measurement change would permanently brick the
+/// sealed volume, since `verify_seal_policy` otherwise only accepts the exact measurement it
+/// was last sealed under.
+async fn migrate_seal(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>> {
+    // Parse request body as both raw bytes (for signature validation) and JSON
+    let payload_raw = body.to_vec();
+    let payload: MigrateSealRequest = serde_json::from_slice(&payload_raw)
+        .map_err(|_| IdentityError::invalid_request("Invalid payload"))?;
+
+    // Verify owner has been registered
+    let owner = state.owner.read().unwrap().clone();
+    let Some(owner) = owner.as_ref() else {
+        return Err(IdentityError::unauthorized("Owner not registered"));
+    };
+
+    // Verify that the signature header matches the nonce-prefixed POST body
+    validate_signature_header(
+        &headers,
+        &method,
+        "/seal/migrate",
+        payload_raw,
+        owner.pubkey,
+        &state.nonces,
+    )?;
+
+    // Validate instance pubkey matches the stored instance pubkey
+    if payload.instance_pubkey != state.instance_pubkey_bytes {
+        return Err(IdentityError::unauthorized("Instance pubkey mismatch"));
+    }
+
+    let migration = SealPolicy {
+        version: payload.version,
+        measurement: payload.measurement,
+        signature: Some(payload.signature),
+    };
+
+    // Authorize and persist the migration, then re-derive and re-mount under it
+    let current = sealing::current_measurement().await?;
+    sealing::verify_seal_policy(&*state.store, &current, Some(&migration), &owner.pubkey).await?;
+    initialize_encryption(&owner.pubkey, &state.ppid, &*state.store).await?;
+
+    Ok(Json(serde_json::json!({ "status": "success" })))
+}
+
+/// Lists every named workload container currently tracked on this instance
+async fn list_workloads(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let workloads = state.workloads.list().await;
+    Json(serde_json::json!(workloads
+        .into_iter()
+        .map(|(name, workload)| serde_json::json!({
+            "name": name,
+            "image": workload.config.image,
+            "image_digest": workload.config.image_digest,
+            "finalized": workload.config.finalized,
+            "host_port": workload.host_port,
+        }))
+        .collect::<Vec<_>>()))
+}
+
+/// Starts (or restarts) a named workload container. Unlike `/workload/configure`, which
+/// manages the one workload an owner configures before finalizing it, this runs the
+/// container immediately as finalized (with its port exposed), and assigns it its own
+/// host port so several named workloads can run side by side.
+async fn start_workload(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>> {
+    // Parse request body as both raw bytes (for signature validation) and JSON
+    let payload_raw = body.to_vec();
+    let payload: ConfigureWorkloadRequest = serde_json::from_slice(&payload_raw)
+        .map_err(|_| IdentityError::invalid_request("Invalid payload"))?;
+
+    // Verify owner has been registered
+    let owner = state.owner.read().unwrap().clone();
+    let Some(owner) = owner.as_ref() else {
+        return Err(IdentityError::unauthorized("Owner not registered"));
+    };
+
+    // Verify that the signature header matches the nonce-prefixed POST body
+    validate_signature_header(
+        &headers,
+        &method,
+        &format!("/workloads/{name}/start"),
+        payload_raw,
+        owner.pubkey,
+        &state.nonces,
+    )?;
+
+    // Validate instance pubkey matches the stored instance pubkey
+    if payload.instance_pubkey != state.instance_pubkey_bytes {
+        return Err(IdentityError::unauthorized("Instance pubkey mismatch"));
+    }
+
+    // Re-adding a dangerous capability requires the owner token on top of the owner's
+    // signature, since a leaked signed request shouldn't be enough to escalate it
+    if contains_dangerous_capability(&payload.cap_add) {
+        validate_owner_token(
+            &headers,
+            &state.instance_pubkey_bytes,
+            &state.used_jtis,
+            CAPABILITY_GRANT_SUBJECT,
+        )?;
+    }
+
+    let image_digest = verify_image_digest(&payload.image, &payload.image_digest).await?;
+
+    let workload_config = WorkloadConfig {
+        image: payload.image,
+        image_digest,
+        persist_dirs: payload.persist_dirs,
+        port: payload.port,
+        finalized: true,
+        memory: payload.memory,
+        memory_swap: payload.memory_swap,
+        cpus: payload.cpus,
+        pids_limit: payload.pids_limit,
+        read_only_rootfs: payload.read_only_rootfs,
+        cap_add: payload.cap_add,
+    };
+
+    let host_port = state.workloads.start(&name, workload_config).await?;
+
+    Ok(Json(serde_json::json!({
+        "status": "success",
+        "host_port": host_port,
+    })))
+}
+
+/// Stops and removes a named workload container
+async fn stop_workload(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>> {
+    // Verify owner has been registered
+    let owner = state.owner.read().unwrap().clone();
+    let Some(owner) = owner.as_ref() else {
+        return Err(IdentityError::unauthorized("Owner not registered"));
+    };
+
+    // There's no body to sign over, so the signature just covers the nonce itself
+    validate_signature_header(
+        &headers,
+        &method,
+        &format!("/workloads/{name}/stop"),
+        Vec::new(),
+        owner.pubkey,
+        &state.nonces,
+    )?;
+
+    state.workloads.stop(&name).await?;
+
     Ok(Json(serde_json::json!({ "status": "success" })))
 }
 
@@ -161,8 +451,13 @@ async fn register_owner(
     headers: HeaderMap,
     Json(request): Json<RegisterIdentityRequest>,
 ) -> Result<Json<serde_json::Value>> {
-    // Verify that the owner token header matches the stored owner token
-    validate_owner_token(&headers, &state.owner_token)?;
+    // Verify that the owner token header matches the one-time token register_operator minted
+    validate_owner_token(
+        &headers,
+        &state.instance_pubkey_bytes,
+        &state.used_jtis,
+        OWNER_REGISTRATION_SUBJECT,
+    )?;
 
     // Verify that the decoded signature is valid and matches the instance public key
     verify_instance_signature(
@@ -174,11 +469,7 @@ async fn register_owner(
     .map_err(IdentityError::unauthorized)?;
 
     // Sign the owner pubkey with the instance pubkey
-    let identity_signature = state
-        .instance_key
-        .try_sign(&request.pubkey)
-        .map_err(IdentityError::internal)?
-        .to_bytes();
+    let identity_signature = state.signer.sign(&request.pubkey).await?;
 
     let owner = IdentityInfo {
         pubkey: request.pubkey,
@@ -187,20 +478,72 @@ async fn register_owner(
     };
 
     // Mount encrypted storage
-    initialize_encryption(&owner.pubkey, &state.ppid).await;
+    initialize_encryption(&owner.pubkey, &state.ppid, &*state.store).await?;
 
     // Save owner to local state and registry
-    state.set_owner(owner)?;
+    state.set_owner(owner).await?;
     state.register_with_registry().await?;
 
     Ok(Json(serde_json::json!({ "status": "success" })))
 }
 
 /// Assigns an operator key to this TDX instance
+///
+/// This only checks a signature, so it's rate-limited two ways: per source address, so
+/// one caller can't hammer it regardless of which pubkey it claims, and per claimed
+/// operator pubkey, so repeated forgery against one identity is throttled even from
+/// rotating addresses. Both back off exponentially on repeated failure and reset on success.
 async fn register_operator(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Json(request): Json<RegisterIdentityRequest>,
 ) -> Result<Json<serde_json::Value>> {
+    let source_ip = addr.ip().to_string();
+    if !state.operator_request_limiter.check(&source_ip) {
+        return Err(IdentityError::too_many_requests(
+            "Too many failed requests from this address, try again later",
+        ));
+    }
+
+    let result = register_operator_checked(&state, &request).await;
+    match &result {
+        Ok(_) => state.operator_request_limiter.record_success(&source_ip),
+        Err(_) => state.operator_request_limiter.record_failure(&source_ip),
+    }
+
+    result.map(Json)
+}
+
+/// The registration logic behind `register_operator`, additionally rate-limited per
+/// claimed operator pubkey
+async fn register_operator_checked(
+    state: &AppState,
+    request: &RegisterIdentityRequest,
+) -> Result<serde_json::Value> {
+    let pubkey_hex = hex::encode(request.pubkey);
+    if !state.operator_signature_limiter.check(&pubkey_hex) {
+        return Err(IdentityError::too_many_requests(
+            "Too many failed attempts for this operator key, try again later",
+        ));
+    }
+
+    let result = register_operator_instance(state, request).await;
+    match &result {
+        Ok(_) => state.operator_signature_limiter.record_success(&pubkey_hex),
+        Err(IdentityError::Unauthorized(_)) => {
+            state.operator_signature_limiter.record_failure(&pubkey_hex)
+        }
+        Err(_) => {}
+    }
+
+    result
+}
+
+/// Verifies the operator signature, signs back, and registers the operator
+async fn register_operator_instance(
+    state: &AppState,
+    request: &RegisterIdentityRequest,
+) -> Result<serde_json::Value> {
     // Don't allow the operator to change once its set
     if state.operator.read().unwrap().is_some() {
         return Err(IdentityError::invalid_request(
@@ -218,11 +561,7 @@ async fn register_operator(
     .map_err(IdentityError::unauthorized)?;
 
     // Sign the operator pubkey with the instance pubkey
-    let identity_signature = state
-        .instance_key
-        .try_sign(&request.pubkey)
-        .map_err(IdentityError::internal)?
-        .to_bytes();
+    let identity_signature = state.signer.sign(&request.pubkey).await?;
 
     let operator = IdentityInfo {
         pubkey: request.pubkey,
@@ -231,13 +570,22 @@ async fn register_operator(
     };
 
     // Save operator to local state and registry
-    state.set_operator(operator)?;
+    state.set_operator(operator).await?;
     state.register_with_registry().await?;
 
-    Ok(Json(serde_json::json!({
+    // Mint a short-lived, single-use capability token granting owner-registration
+    // authority, rather than handing out a persisted shared secret
+    let owner_token = mint_owner_token(
+        &*state.signer,
+        &state.instance_pubkey_bytes,
+        OWNER_REGISTRATION_SUBJECT,
+    )
+    .await?;
+
+    Ok(serde_json::json!({
         "status": "success",
-        "owner_token": state.owner_token,
-    })))
+        "owner_token": owner_token,
+    }))
 }
 
 /// Returns the instance public key
@@ -247,3 +595,12 @@ async fn get_instance_pubkey(State(state): State<Arc<AppState>>) -> Json<serde_j
         "pubkey": hex::encode(&state.instance_pubkey_bytes)
     }))
 }
+
+/// Issues a single-use, short-lived nonce that must be included (via the x-nonce header)
+/// and signed over alongside the body of any request validated by `validate_signature_header`
+async fn issue_challenge(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let nonce = state.nonces.issue();
+    Json(serde_json::json!({
+        "nonce": hex::encode(nonce)
+    }))
+}
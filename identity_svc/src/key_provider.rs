@@ -0,0 +1,151 @@
+//! Pluggable key-derivation backends for the gocryptfs encryption passphrase
+//!
+//! `encryption.rs` used to hash `owner_pubkey || ppid || measurement` locally, which means
+//! anyone who learns those (mostly public) values can derive the disk key themselves. The
+//! `KeyProvider` trait lets that be replaced with a networked key service: the instance
+//! proves it's running inside a specific attested environment with a fresh TDX quote and
+//! establishes an ephemeral ECDH channel to the key server, which only releases (or
+//! threshold-reconstructs) the key material once the quote checks out, wrapping it so only
+//! the instance holding the matching ephemeral secret can read it back.
+
+use crate::{error::IdentityError, error::Result, tdx::create_tdx_quote};
+use async_trait::async_trait;
+use crypto_secretbox::{
+    aead::{Aead, KeyInit},
+    Nonce, XSalsa20Poly1305,
+};
+use hkdf::Hkdf;
+use rand_core::OsRng;
+use reqwest::Client;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env::var;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// A pluggable source of the gocryptfs encryption passphrase
+#[async_trait]
+pub trait KeyProvider: Send + Sync {
+    /// Derives the gocryptfs passphrase for the given owner pubkey, PPID, and measurement
+    async fn derive_key(&self, owner_pubkey: &[u8], ppid: &[u8], measurement: &[u8]) -> Result<String>;
+}
+
+/// Builds the key provider selected by the `KEY_PROVIDER` env var
+/// Defaults to `DevKeyProvider`, which derives the key locally with no external dependency
+pub fn build_key_provider() -> Box<dyn KeyProvider> {
+    match var("KEY_PROVIDER").as_deref() {
+        Ok("remote") => Box::new(RemoteKeyProvider::from_env()),
+        _ => Box::new(DevKeyProvider),
+    }
+}
+
+/// Derives the key locally by hashing public inputs
+///
+/// This is the historical behavior of the identity service. It's kept as a development
+/// fallback that needs no external key service, even though the resulting key depends only
+/// on values that aren't actually secret.
+pub struct DevKeyProvider;
+
+#[async_trait]
+impl KeyProvider for DevKeyProvider {
+    async fn derive_key(&self, owner_pubkey: &[u8], ppid: &[u8], measurement: &[u8]) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(owner_pubkey);
+        hasher.update(ppid);
+        hasher.update(measurement);
+        Ok(hex::encode(hasher.finalize()))
+    }
+}
+
+/// Requests the key from an external key server, selected via `KEY_PROVIDER=remote` and
+/// configured with `KEY_SERVER_URL`
+pub struct RemoteKeyProvider {
+    url: String,
+    client: Client,
+}
+
+impl RemoteKeyProvider {
+    /// # Panics
+    ///
+    /// Panics if `KEY_SERVER_URL` is unset, since this only runs once at startup when
+    /// `KEY_PROVIDER=remote` is explicitly requested
+    pub fn from_env() -> Self {
+        let url =
+            var("KEY_SERVER_URL").expect("KEY_SERVER_URL must be set when KEY_PROVIDER=remote");
+        Self {
+            url,
+            client: Client::new(),
+        }
+    }
+}
+
+/// Response from the key server's `/key` endpoint: the key material wrapped with the
+/// shared secret from the server's ephemeral keypair and the one this request generated
+#[derive(Deserialize)]
+struct KeyResponse {
+    #[serde(with = "shared::types::hex_serde")]
+    server_public: [u8; 32],
+    #[serde(with = "shared::types::hex_serde")]
+    nonce: [u8; 24],
+    ciphertext: String,
+}
+
+#[async_trait]
+impl KeyProvider for RemoteKeyProvider {
+    async fn derive_key(&self, owner_pubkey: &[u8], ppid: &[u8], measurement: &[u8]) -> Result<String> {
+        // The key identifier doesn't need to be secret, only stable: it's how the key
+        // server looks up (or re-derives) the same key material on every request
+        let key_id = {
+            let mut hasher = Sha256::new();
+            hasher.update(owner_pubkey);
+            hasher.update(ppid);
+            hex::encode(hasher.finalize())
+        };
+
+        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral_secret);
+
+        // Bind the quote to this specific request (the key id and our ephemeral public
+        // key) so it can't be replayed against a different request or forwarded on behalf
+        // of a different instance
+        let mut report_data = [0u8; 64];
+        let mut hasher = Sha256::new();
+        hasher.update(key_id.as_bytes());
+        hasher.update(ephemeral_public.as_bytes());
+        hasher.update(measurement);
+        report_data[..32].copy_from_slice(&hasher.finalize());
+        let quote = create_tdx_quote(report_data).await?;
+
+        let response: KeyResponse = self
+            .client
+            .post(format!("{}/key", self.url))
+            .json(&serde_json::json!({
+                "key_id": key_id,
+                "ephemeral_public": hex::encode(ephemeral_public.as_bytes()),
+                "quote": hex::encode(&quote),
+            }))
+            .send()
+            .await
+            .map_err(|e| IdentityError::internal(format!("Failed to reach key server: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| IdentityError::internal(format!("Invalid key server response: {}", e)))?;
+
+        let shared_secret =
+            ephemeral_secret.diffie_hellman(&PublicKey::from(response.server_public));
+
+        let mut wrap_key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, shared_secret.as_bytes())
+            .expand(b"tdx-identity-key-wrap", &mut wrap_key)
+            .map_err(|_| IdentityError::internal("Failed to derive key-wrap secret"))?;
+        let cipher = XSalsa20Poly1305::new_from_slice(&wrap_key)
+            .map_err(|_| IdentityError::internal("Invalid key-wrap secret"))?;
+
+        let ciphertext = hex::decode(&response.ciphertext)
+            .map_err(|_| IdentityError::internal("Invalid key server ciphertext"))?;
+        let key_material = cipher
+            .decrypt(Nonce::from_slice(&response.nonce), ciphertext.as_slice())
+            .map_err(|_| IdentityError::internal("Failed to unwrap key material"))?;
+
+        Ok(hex::encode(key_material))
+    }
+}
@@ -1,16 +1,25 @@
 use crate::{handlers::create_router, state::AppState};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 
 mod encryption;
 mod error;
 mod handlers;
+mod key_provider;
+mod nonce;
+mod registry_client;
+mod sealing;
+mod signer;
 mod ssh;
 mod state;
 mod storage;
+mod store;
 mod tdx;
+mod token;
 mod validation;
 mod workload;
+mod workloads;
 
 #[tokio::main]
 async fn main() {
@@ -18,5 +27,10 @@ async fn main() {
     let app = create_router(Arc::new(state));
     println!("Starting server on 0.0.0.0:3001");
     let listener = TcpListener::bind("0.0.0.0:3001").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -0,0 +1,85 @@
+//! Replay-resistant nonces for the request signing scheme
+//!
+//! `validate_signature_header` signs over `nonce || body` instead of just `body`, so a
+//! captured request/signature pair can't be replayed: each nonce is issued by `POST
+//! /challenge`, is single-use, and expires after a short TTL.
+
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    env::var,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    /// How long an issued nonce remains valid if unused
+    static ref NONCE_TTL: Duration = Duration::from_secs(
+        var("NONCE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30)
+    );
+    /// How often the background sweep clears out expired, unconsumed nonces
+    static ref NONCE_SWEEP_INTERVAL: Duration = Duration::from_secs(
+        var("NONCE_SWEEP_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60)
+    );
+}
+
+/// A set of issued, not-yet-consumed nonces and their expiry times
+pub struct NonceStore {
+    issued: RwLock<HashMap<[u8; 32], Instant>>,
+}
+
+impl NonceStore {
+    pub fn new() -> Self {
+        Self {
+            issued: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Issues a fresh random nonce, valid for `NONCE_TTL`
+    pub fn issue(&self) -> [u8; 32] {
+        let nonce = rand::random::<[u8; 32]>();
+        self.issued
+            .write()
+            .expect("Failed to acquire write lock on nonces")
+            .insert(nonce, Instant::now() + *NONCE_TTL);
+        nonce
+    }
+
+    /// Consumes `nonce`, returning whether it existed and hadn't expired. Either way, the
+    /// nonce is removed so it cannot be checked again.
+    pub fn consume(&self, nonce: &[u8; 32]) -> bool {
+        let expires_at = self
+            .issued
+            .write()
+            .expect("Failed to acquire write lock on nonces")
+            .remove(nonce);
+
+        matches!(expires_at, Some(expires_at) if Instant::now() < expires_at)
+    }
+
+    /// Drops every expired, unconsumed nonce. Consuming already does this lazily for
+    /// individual nonces; this is for nonces that are issued but never used at all.
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        self.issued
+            .write()
+            .expect("Failed to acquire write lock on nonces")
+            .retain(|_, expires_at| *expires_at > now);
+    }
+
+    /// Spawns a background task that periodically sweeps out expired, unconsumed nonces
+    pub fn spawn_sweeper(self: Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(*NONCE_SWEEP_INTERVAL).await;
+                self.sweep();
+            }
+        });
+    }
+}
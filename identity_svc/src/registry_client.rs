@@ -0,0 +1,182 @@
+//! Resolves and pins the OCI image digest a workload is configured with
+//!
+//! `WorkloadConfig::image` used to be a mutable tag, so the same configuration could
+//! silently resolve to a different set of image bytes on re-pull, and nothing about the
+//! image entered the attestation quote. This module fetches the manifest for an image
+//! reference from its OCI Registry v2 distribution endpoint, and checks the registry's
+//! reported digest against the digest the caller pinned, so `configure_workload` can fold
+//! the exact image bytes into `create_attestation_hash`.
+
+use crate::error::{IdentityError, Result};
+use reqwest::{header, Client};
+use sha2::{Digest, Sha256};
+
+/// Manifest media types accepted when resolving an image's digest, covering both the OCI
+/// and legacy Docker manifest (and manifest list) formats
+const MANIFEST_ACCEPT: &str = concat!(
+    "application/vnd.oci.image.manifest.v1+json, ",
+    "application/vnd.oci.image.index.v1+json, ",
+    "application/vnd.docker.distribution.manifest.v2+json, ",
+    "application/vnd.docker.distribution.manifest.list.v2+json"
+);
+
+/// An image reference split into its registry host, repository name, and tag/digest
+struct ImageRef {
+    registry: String,
+    name: String,
+    reference: String,
+}
+
+/// Parses an image string like `nginx`, `nginx:1.27`, or `ghcr.io/org/repo:tag` into its
+/// registry, repository name, and reference, defaulting to Docker Hub and the `library/`
+/// namespace the way `docker pull` does for unqualified references
+fn parse_image_ref(image: &str) -> ImageRef {
+    let (host_part, rest) = match image.split_once('/') {
+        Some((host, rest)) if host.contains('.') || host.contains(':') || host == "localhost" => {
+            (host, rest)
+        }
+        _ => ("registry-1.docker.io", image),
+    };
+
+    let (name, reference) = match rest.rsplit_once(':') {
+        // Guard against a ':' that's actually part of a registry port, e.g. `localhost:5000/foo`
+        Some((name, reference)) if !name.is_empty() && !reference.contains('/') => {
+            (name.to_string(), reference.to_string())
+        }
+        _ => (rest.to_string(), "latest".to_string()),
+    };
+
+    let name = if host_part == "registry-1.docker.io" && !name.contains('/') {
+        format!("library/{name}")
+    } else {
+        name
+    };
+
+    ImageRef {
+        registry: host_part.to_string(),
+        name,
+        reference,
+    }
+}
+
+/// Fetches `image`'s manifest from its registry and checks that its digest matches
+/// `expected_digest` (a `sha256:...` string), returning the verified digest
+///
+/// # Errors
+///
+/// Returns `IdentityError::invalid_request` if the manifest can't be fetched, or if its
+/// digest doesn't match `expected_digest`
+pub async fn verify_image_digest(image: &str, expected_digest: &str) -> Result<String> {
+    let image_ref = parse_image_ref(image);
+    let client = Client::new();
+    let url = format!(
+        "https://{}/v2/{}/manifests/{}",
+        image_ref.registry, image_ref.name, image_ref.reference
+    );
+
+    let response = fetch_manifest(&client, &url, None).await?;
+    let response = match response.status() {
+        reqwest::StatusCode::UNAUTHORIZED => {
+            let token = fetch_pull_token(&client, &response, &image_ref).await?;
+            fetch_manifest(&client, &url, Some(&token)).await?
+        }
+        _ => response,
+    };
+
+    if !response.status().is_success() {
+        return Err(IdentityError::invalid_request(format!(
+            "Failed to fetch manifest for {image}: HTTP {}",
+            response.status()
+        )));
+    }
+
+    // Prefer the registry's own `Docker-Content-Digest` header; fall back to hashing the
+    // manifest body ourselves, since that's exactly what the header is supposed to contain
+    let reported_digest = response
+        .headers()
+        .get("Docker-Content-Digest")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| IdentityError::invalid_request(format!("Failed to read manifest: {e}")))?;
+    let computed_digest = format!("sha256:{}", hex::encode(Sha256::digest(&body)));
+
+    let digest = reported_digest.unwrap_or(computed_digest.clone());
+    if digest != computed_digest && reported_digest.is_some() {
+        return Err(IdentityError::invalid_request(
+            "Registry-reported digest doesn't match the manifest body",
+        ));
+    }
+
+    if digest != expected_digest {
+        return Err(IdentityError::invalid_request(format!(
+            "Image {image} resolved to {digest}, not the pinned digest {expected_digest}"
+        )));
+    }
+
+    Ok(digest)
+}
+
+async fn fetch_manifest(client: &Client, url: &str, token: Option<&str>) -> Result<reqwest::Response> {
+    let mut request = client.get(url).header(header::ACCEPT, MANIFEST_ACCEPT);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    request
+        .send()
+        .await
+        .map_err(|e| IdentityError::invalid_request(format!("Failed to reach registry: {e}")))
+}
+
+/// Requests an anonymous pull token from the realm advertised by a manifest request's
+/// `WWW-Authenticate` challenge, following the standard Docker Registry token auth flow
+/// used by Docker Hub, GHCR, and most other registries
+async fn fetch_pull_token(
+    client: &Client,
+    challenge_response: &reqwest::Response,
+    image_ref: &ImageRef,
+) -> Result<String> {
+    let challenge = challenge_response
+        .headers()
+        .get(header::WWW_AUTHENTICATE)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| IdentityError::invalid_request("Registry did not advertise a token realm"))?;
+
+    let realm = parse_challenge_param(challenge, "realm")
+        .ok_or_else(|| IdentityError::invalid_request("Missing token realm in auth challenge"))?;
+    let service = parse_challenge_param(challenge, "service");
+
+    let mut query = vec![("scope", format!("repository:{}:pull", image_ref.name))];
+    if let Some(service) = service {
+        query.push(("service", service));
+    }
+
+    let response: serde_json::Value = client
+        .get(realm)
+        .query(&query)
+        .send()
+        .await
+        .map_err(|e| IdentityError::invalid_request(format!("Failed to reach auth realm: {e}")))?
+        .json()
+        .await
+        .map_err(|e| IdentityError::invalid_request(format!("Invalid auth realm response: {e}")))?;
+
+    response["token"]
+        .as_str()
+        .or_else(|| response["access_token"].as_str())
+        .map(str::to_string)
+        .ok_or_else(|| IdentityError::invalid_request("Auth realm did not return a token"))
+}
+
+/// Extracts a quoted `key="value"` parameter from a `WWW-Authenticate: Bearer ...` header
+fn parse_challenge_param(challenge: &str, key: &str) -> Option<String> {
+    challenge.split(',').find_map(|part| {
+        let part = part.trim().trim_start_matches("Bearer").trim();
+        part.strip_prefix(&format!("{key}=\""))
+            .and_then(|rest| rest.strip_suffix('"'))
+            .map(str::to_string)
+    })
+}
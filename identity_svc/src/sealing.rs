@@ -0,0 +1,164 @@
+//! Anti-rollback sealed storage
+//!
+//! Binds the disk encryption key to the TD's current measurement registers (MRTD plus
+//! RTMR0-2) in addition to the owner pubkey and PPID, and tracks a monotonic version
+//! counter alongside them. A TD can only unseal data sealed under version N and
+//! measurement M by presenting measurement M (or an authorized successor via a
+//! signed migration) and a version >= N, so a rolled-back or tampered workload image
+//! cannot unseal data sealed under a newer one.
+
+use crate::error::{IdentityError, Result};
+use crate::store::StateStore;
+use crate::tdx::create_tdx_quote;
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tdx_quote::Quote;
+
+const SEAL_POLICY_KEY: &str = "seal_policy.json";
+
+/// A digest of the TD's measurement registers at the time a volume was sealed
+pub type Measurement = Vec<u8>;
+
+/// The unencrypted policy persisted alongside a sealed volume
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SealPolicy {
+    pub version: u64,
+    pub measurement: Measurement,
+    /// The owner's signature over `signing_bytes(version, measurement)`. Required on any
+    /// policy presented as a migration (a measurement change); `None` for policies sealed
+    /// locally from the TD's own state, on first boot or via `reseal_for_new_workload`.
+    #[serde(default)]
+    pub signature: Option<[u8; SIGNATURE_LENGTH]>,
+}
+
+/// The bytes an owner's migration signature covers
+fn signing_bytes(version: u64, measurement: &Measurement) -> Vec<u8> {
+    let mut bytes = version.to_le_bytes().to_vec();
+    bytes.extend_from_slice(measurement);
+    bytes
+}
+
+/// Reads the TD's current measurement registers by creating a quote and hashing its body
+pub async fn current_measurement() -> Result<Measurement> {
+    let quote_bytes = create_tdx_quote([0u8; 64]).await?;
+    let quote = Quote::from_bytes(&quote_bytes)
+        .map_err(|e| IdentityError::internal(format!("Failed to parse quote: {}", e)))?;
+    Ok(measurement_from_quote(&quote))
+}
+
+/// Digests MRTD and RTMR0-2 together into a single measurement
+/// RTMR3 is left out since it covers post-boot runtime event logs, not the image itself
+fn measurement_from_quote(quote: &Quote) -> Measurement {
+    let mut hasher = Sha256::new();
+    hasher.update(quote.mrtd);
+    hasher.update(quote.rtmr0);
+    hasher.update(quote.rtmr1);
+    hasher.update(quote.rtmr2);
+    hasher.finalize().to_vec()
+}
+
+/// Loads the persisted seal policy, if any
+pub async fn get_seal_policy(store: &dyn StateStore) -> Result<Option<SealPolicy>> {
+    match store.get(SEAL_POLICY_KEY).await? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(IdentityError::internal)
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Persists the seal policy
+async fn store_seal_policy(store: &dyn StateStore, policy: &SealPolicy) -> Result<()> {
+    let data = serde_json::to_vec_pretty(policy).map_err(IdentityError::internal)?;
+    store.put(SEAL_POLICY_KEY, data).await
+}
+
+/// Checks `current` against the stored seal policy, sealing fresh on first boot
+///
+/// # Errors
+///
+/// Returns an unauthorized error if the measurement differs from the stored policy and
+/// `migration` doesn't authorize it, or if the migration's version is lower than the
+/// stored version (a rollback attempt)
+pub async fn verify_seal_policy(
+    store: &dyn StateStore,
+    current: &Measurement,
+    migration: Option<&SealPolicy>,
+    owner_pubkey_bytes: &[u8; PUBLIC_KEY_LENGTH],
+) -> Result<SealPolicy> {
+    let Some(stored) = get_seal_policy(store).await? else {
+        // First boot: seal fresh under the current measurement at version 0
+        let policy = SealPolicy {
+            version: 0,
+            measurement: current.clone(),
+            signature: None,
+        };
+        store_seal_policy(store, &policy).await?;
+        return Ok(policy);
+    };
+
+    if &stored.measurement == current {
+        return Ok(stored);
+    }
+
+    // Measurement differs: only proceed if an owner-signed migration authorizes it
+    if let Some(migration) = migration {
+        verify_migration_signature(migration, owner_pubkey_bytes)?;
+
+        if &migration.measurement == current && migration.version >= stored.version {
+            store_seal_policy(store, migration).await?;
+            return Ok(migration.clone());
+        }
+        if migration.version < stored.version {
+            return Err(IdentityError::unauthorized(
+                "Migration version is older than the sealed policy",
+            ));
+        }
+    }
+
+    Err(IdentityError::unauthorized(
+        "TD measurement does not match the sealed policy and no valid migration was presented",
+    ))
+}
+
+/// Verifies a migration's signature was produced by the owner over its own version and
+/// measurement, so an unsigned or forged migration can't move the seal off its measurement
+fn verify_migration_signature(
+    migration: &SealPolicy,
+    owner_pubkey_bytes: &[u8; PUBLIC_KEY_LENGTH],
+) -> Result<()> {
+    let Some(signature_bytes) = migration.signature else {
+        return Err(IdentityError::unauthorized(
+            "Migration is not signed by the owner",
+        ));
+    };
+
+    let verifying_key = VerifyingKey::from_bytes(owner_pubkey_bytes)
+        .map_err(|_| IdentityError::internal("Invalid owner public key"))?;
+    let signature = Signature::from_slice(&signature_bytes)
+        .map_err(|_| IdentityError::internal("Invalid migration signature encoding"))?;
+
+    verifying_key
+        .verify_strict(&signing_bytes(migration.version, &migration.measurement), &signature)
+        .map_err(|_| IdentityError::unauthorized("Invalid migration signature"))
+}
+
+/// Bumps the seal policy's version and re-seals under the current measurement
+/// Called when the owner finalizes a new workload
+pub async fn reseal_for_new_workload(
+    store: &dyn StateStore,
+    current: &Measurement,
+) -> Result<SealPolicy> {
+    let next_version = match get_seal_policy(store).await? {
+        Some(policy) => policy.version + 1,
+        None => 0,
+    };
+    let policy = SealPolicy {
+        version: next_version,
+        measurement: current.clone(),
+        signature: None,
+    };
+    store_seal_policy(store, &policy).await?;
+    Ok(policy)
+}
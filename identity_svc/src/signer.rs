@@ -0,0 +1,136 @@
+//! Pluggable signer backends for the instance key
+//!
+//! Every handler that proves control of the instance key goes through the `Signer` trait
+//! instead of holding a `SigningKey` directly, so the key doesn't have to live in this
+//! process: `RemoteSigner` delegates to an external signing service (an HSM-backed daemon,
+//! for example) over HTTP, while `LocalSigner` keeps the historical in-process behavior.
+
+use crate::{
+    error::{IdentityError, Result},
+    storage::get_or_create_instance_key,
+    store::StateStore,
+};
+use async_trait::async_trait;
+use ed25519_dalek::{Signer as _, SigningKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use reqwest::Client;
+use std::env::var;
+
+/// A pluggable source of signatures under the instance key
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// Signs `msg` and returns the raw ed25519 signature bytes
+    async fn sign(&self, msg: &[u8]) -> Result<[u8; SIGNATURE_LENGTH]>;
+
+    /// Returns the public key corresponding to the signing key this `Signer` holds or
+    /// delegates to
+    fn public_key(&self) -> [u8; PUBLIC_KEY_LENGTH];
+}
+
+/// Builds the signer backend selected by the `SIGNER_BACKEND` env var
+/// Defaults to the filesystem-persisted `LocalSigner`
+pub async fn build_signer(store: &dyn StateStore) -> Result<Box<dyn Signer>> {
+    match var("SIGNER_BACKEND").as_deref() {
+        Ok("remote") => {
+            let url = var("REMOTE_SIGNER_URL").map_err(|_| {
+                IdentityError::internal("REMOTE_SIGNER_URL must be set when SIGNER_BACKEND=remote")
+            })?;
+            Ok(Box::new(RemoteSigner::new(url).await?))
+        }
+        _ => Ok(Box::new(LocalSigner::new(
+            get_or_create_instance_key(store).await?,
+        ))),
+    }
+}
+
+/// Signs with a `SigningKey` held in process memory
+///
+/// This is the historical behavior of the identity service
+pub struct LocalSigner {
+    key: SigningKey,
+}
+
+impl LocalSigner {
+    pub fn new(key: SigningKey) -> Self {
+        Self { key }
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    async fn sign(&self, msg: &[u8]) -> Result<[u8; SIGNATURE_LENGTH]> {
+        Ok(self
+            .key
+            .try_sign(msg)
+            .map_err(IdentityError::internal)?
+            .to_bytes())
+    }
+
+    fn public_key(&self) -> [u8; PUBLIC_KEY_LENGTH] {
+        self.key.verifying_key().to_bytes()
+    }
+}
+
+/// Signs by delegating to an external signing service over HTTP, selected via
+/// `SIGNER_BACKEND=remote` and configured with `REMOTE_SIGNER_URL`
+///
+/// This keeps the instance key out of this process entirely, so it can live in an HSM or a
+/// dedicated signing daemon instead. The public key is fetched once at startup via
+/// `GET {url}/pubkey`; each signature is requested via `POST {url}/sign`.
+pub struct RemoteSigner {
+    url: String,
+    public_key: [u8; PUBLIC_KEY_LENGTH],
+    client: Client,
+}
+
+impl RemoteSigner {
+    pub async fn new(url: String) -> Result<Self> {
+        let client = Client::new();
+
+        let response: serde_json::Value = client
+            .get(format!("{url}/pubkey"))
+            .send()
+            .await
+            .map_err(|e| IdentityError::internal(format!("Failed to reach remote signer: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| IdentityError::internal(format!("Invalid remote signer response: {}", e)))?;
+
+        let public_key = response["pubkey"]
+            .as_str()
+            .and_then(|s| hex::decode(s).ok())
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| IdentityError::internal("Remote signer returned an invalid pubkey"))?;
+
+        Ok(Self {
+            url,
+            public_key,
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    async fn sign(&self, msg: &[u8]) -> Result<[u8; SIGNATURE_LENGTH]> {
+        let response: serde_json::Value = self
+            .client
+            .post(format!("{}/sign", self.url))
+            .json(&serde_json::json!({ "message": hex::encode(msg) }))
+            .send()
+            .await
+            .map_err(|e| IdentityError::internal(format!("Failed to reach remote signer: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| IdentityError::internal(format!("Invalid remote signer response: {}", e)))?;
+
+        response["signature"]
+            .as_str()
+            .and_then(|s| hex::decode(s).ok())
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| IdentityError::internal("Remote signer returned an invalid signature"))
+    }
+
+    fn public_key(&self) -> [u8; PUBLIC_KEY_LENGTH] {
+        self.public_key
+    }
+}
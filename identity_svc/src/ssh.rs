@@ -2,29 +2,71 @@
 //!
 //! This module implements an SSH server that authenticates users using Ed25519 public key
 //! authentication and provides them with shell access to a specified Podman container.
-//! The server runs on port 2222 and maintains a single persistent connection to a podman
-//! shell per authenticated session.
+//! The server runs on port 2222. The shell is attached to a real pseudo-terminal, so
+//! interactive programs (vim, top, less) get a TTY and the correct terminal size, which is
+//! kept in sync with the client's window as it resizes. Shells are persistent across
+//! disconnects: a dropped connection detaches from its podman shell instead of killing it,
+//! and reconnecting with the same key resumes it, replaying recent scrollback.
+//!
+//! A client targets a specific named workload container by running `exec <name>` instead
+//! of requesting a plain shell; a plain shell request attaches to the default workload.
 
-use crate::workload::{CONTAINER_NAME, PODMAN_SOCKET_PATH};
+use crate::workload::{container_ip, CONTAINER_NAME, PODMAN_SOCKET_PATH};
 use async_trait::async_trait;
 use ed25519_dalek::{VerifyingKey, PUBLIC_KEY_LENGTH};
+use lazy_static::lazy_static;
+use portable_pty::{native_pty_system, Child as PtyChild, CommandBuilder, MasterPty, PtySize};
 use russh::{
-    server::{run_stream, Auth, Config, Msg, Session},
-    Channel, ChannelId, CryptoVec, MethodSet,
+    server::{run_stream, Auth, Config, Handle, Msg, Session},
+    Channel, ChannelId, CryptoVec, MethodSet, Pty,
 };
 use russh_keys::key::{KeyPair, PublicKey};
-use std::{process::Stdio, sync::Arc};
-use std::{sync::OnceLock, time::Duration};
+use std::collections::{HashMap, VecDeque};
+use std::env::var;
+use std::io::{Read, Write};
+use std::time::Instant;
+use std::{sync::Arc, sync::OnceLock, time::Duration};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpListener,
-    process::{Child, Command},
-    sync::{broadcast, Mutex},
+    io::copy_bidirectional,
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc, Mutex},
+    task::JoinHandle,
 };
 
 /// Global shutdown signal sender for gracefully stopping the SSH server
 static SHUTDOWN: OnceLock<broadcast::Sender<()>> = OnceLock::new();
 const SSH_PORT: u16 = 2222;
+/// Terminal size used if a shell is requested without a prior `pty_request`
+const DEFAULT_PTY_SIZE: PtySize = PtySize {
+    rows: 24,
+    cols: 80,
+    pixel_width: 0,
+    pixel_height: 0,
+};
+/// How much recent output is kept so a reconnecting client can see what it missed
+const SCROLLBACK_CAPACITY: usize = 64 * 1024;
+
+lazy_static! {
+    /// How long a detached (disconnected) shell is kept running before it's killed
+    static ref DETACHED_SESSION_TTL: Duration = Duration::from_secs(
+        var("DETACHED_SESSION_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1800)
+    );
+}
+
+/// A live shell's key in the session registry: the authenticated owner's public key and
+/// the name of the workload container it's attached to
+type SessionKey = ([u8; PUBLIC_KEY_LENGTH], String);
+
+/// Persistent shells, keyed by owner public key and target workload name, that survive a
+/// dropped SSH connection so a reconnecting client can resume them
+static SESSIONS: OnceLock<Mutex<HashMap<SessionKey, Arc<Mutex<LiveSession>>>>> = OnceLock::new();
+
+fn sessions() -> &'static Mutex<HashMap<SessionKey, Arc<Mutex<LiveSession>>>> {
+    SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
 /// Starts the SSH server with the specified Ed25519 public key for authentication.
 ///
@@ -100,7 +142,10 @@ async fn handle_incoming_connections(
             Ok((socket, addr)) = listener.accept() => {
                 let handler = Handler {
                     pubkey,
-                    shell: Arc::new(Mutex::new(None)),
+                    current: Arc::new(Mutex::new(None)),
+                    pending_size: Arc::new(Mutex::new(DEFAULT_PTY_SIZE)),
+                    target_workload: Arc::new(Mutex::new(CONTAINER_NAME.to_string())),
+                    forwards: Arc::new(Mutex::new(Vec::new())),
                     shutdown: shutdown_rx.resubscribe(),
                 };
 
@@ -117,48 +162,174 @@ async fn handle_incoming_connections(
     }
 }
 
-/// Pipes I/O between the Podman shell and SSH channel.
-async fn handle_shell_io(
-    mut stdout: impl AsyncReadExt + Unpin,
-    mut stderr: impl AsyncReadExt + Unpin,
+/// A fixed-capacity byte buffer that drops the oldest bytes once full, so a reconnecting
+/// client can be shown recent output without retaining the shell's entire history
+struct RingBuffer {
+    buf: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buf: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend(data);
+        while self.buf.len() > self.capacity {
+            self.buf.pop_front();
+        }
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.buf.iter().copied().collect()
+    }
+}
+
+/// The SSH channel currently attached to a live session, if a client is connected to it
+struct AttachedChannel {
     channel_id: ChannelId,
-    session: russh::server::Handle,
-    mut shutdown: broadcast::Receiver<()>,
+    handle: Handle,
+}
+
+/// A podman shell and its pseudo-terminal that outlives any single SSH connection
+struct LiveSession {
+    pty: Box<dyn MasterPty + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Box<dyn PtyChild + Send + Sync>,
+    scrollback: Arc<Mutex<RingBuffer>>,
+    /// Replaced each time a client (re)attaches, cleared to `None` on detach
+    attached: Arc<Mutex<Option<AttachedChannel>>>,
+    last_active: Instant,
+}
+
+/// Spawns a podman shell into the named workload container, attached to a new PTY of the
+/// given size, returning the session and starting the background task that drains its
+/// output into the scrollback buffer and, while a client is attached, forwards it live
+/// over the SSH channel.
+fn spawn_live_session(size: PtySize, workload: &str) -> Result<LiveSession, russh::Error> {
+    let pty_system = native_pty_system();
+    let pair = pty_system
+        .openpty(size)
+        .map_err(|_| russh::Error::Disconnect)?;
+
+    let sock = format!("unix://{PODMAN_SOCKET_PATH}");
+    let mut cmd = CommandBuilder::new("podman");
+    cmd.args(["--url", &sock, "exec", "-it", workload, "/bin/sh"]);
+
+    let child = pair
+        .slave
+        .spawn_command(cmd)
+        .map_err(|_| russh::Error::Disconnect)?;
+    // Drop our copy of the slave so the child holds the only reference to it
+    drop(pair.slave);
+
+    let reader = pair
+        .master
+        .try_clone_reader()
+        .map_err(|_| russh::Error::Disconnect)?;
+    let writer = pair
+        .master
+        .take_writer()
+        .map_err(|_| russh::Error::Disconnect)?;
+
+    let scrollback = Arc::new(Mutex::new(RingBuffer::new(SCROLLBACK_CAPACITY)));
+    let attached = Arc::new(Mutex::new(None));
+    spawn_pty_reader(reader, scrollback.clone(), attached.clone());
+
+    Ok(LiveSession {
+        pty: pair.master,
+        writer,
+        child,
+        scrollback,
+        attached,
+        last_active: Instant::now(),
+    })
+}
+
+/// Drains PTY output for the lifetime of the child process, recording it into
+/// `scrollback` and forwarding it to whichever channel is currently `attached`, if any.
+///
+/// The PTY's reader is a blocking `std::io::Read`, so it's driven from a dedicated OS
+/// thread and forwarded to this async task over a channel.
+fn spawn_pty_reader(
+    mut reader: Box<dyn Read + Send>,
+    scrollback: Arc<Mutex<RingBuffer>>,
+    attached: Arc<Mutex<Option<AttachedChannel>>>,
 ) {
-    let mut stdout_buf = [0u8; 1024];
-    let mut stderr_buf = [0u8; 1024];
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(64);
 
-    // Send shell output to the SSH channel until the channel is closed
-    loop {
-        tokio::select! {
-            Ok(n) = stdout.read(&mut stdout_buf) => {
-                if n == 0 || session.data(channel_id, CryptoVec::from(stdout_buf[..n].to_vec())).await.is_err() {
-                    break;
-                }
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) if tx.blocking_send(buf[..n].to_vec()).is_err() => break,
+                Ok(_) => {}
             }
-            Ok(n) = stderr.read(&mut stderr_buf) => {
-                if n == 0 || session.data(channel_id, CryptoVec::from(stderr_buf[..n].to_vec())).await.is_err() {
-                    break;
-                }
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(chunk) = rx.recv().await {
+            scrollback.lock().await.push(&chunk);
+            if let Some(current) = &*attached.lock().await {
+                let _ = current
+                    .handle
+                    .data(current.channel_id, CryptoVec::from(chunk))
+                    .await;
             }
-            _ = shutdown.recv() => break
         }
-    }
-    let _ = session.close(channel_id).await;
+        // The shell exited: close out whichever channel is currently watching it
+        if let Some(current) = attached.lock().await.take() {
+            let _ = current.handle.close(current.channel_id).await;
+        }
+    });
+}
+
+/// Which side initiated a forwarded TCP tunnel. Kept as an enum (rather than two separate
+/// code paths) so a UDP variant of either direction can be added later.
+enum ForwardKind {
+    /// `direct-tcpip`: the client connects to a host:port reachable from the container
+    LocalToRemoteTcp,
+    /// `tcpip-forward`: the server listens and forwards accepted connections to the client
+    RemoteToLocalTcp,
+}
+
+/// A forwarded TCP tunnel that's currently running in the background
+struct ActiveForward {
+    kind: ForwardKind,
+    address: String,
+    port: u32,
+    task: JoinHandle<()>,
 }
 
 /// SSH server session handler that manages authentication and shell sessions.
 ///
 /// The handler:
 /// * Verifies client public keys against the configured key
-/// * Spawns a Podman shell for authenticated sessions
-/// * Manages I/O between the SSH channel and Podman shell
-/// * Handles cleanup when sessions end
+/// * Attaches to a persistent podman shell for the authenticated key, spawning one on a
+///   pseudo-terminal if none is running yet, and replays recent scrollback on reattach
+/// * Resizes the active pseudo-terminal as the client's window changes
+/// * Tunnels local and remote TCP port forwards, resolving a workload container's name to
+///   its container IP for local forwards
+/// * Detaches (rather than kills) the shell when the connection drops, so it can be
+///   resumed later, only tearing it down after it's been idle past `DETACHED_SESSION_TTL`
 struct Handler {
     /// The public key used to verify client connections
     pubkey: VerifyingKey,
-    /// The currently active shell process, if any
-    shell: Arc<Mutex<Option<Child>>>,
+    /// The live session this connection is currently attached to, if any
+    current: Arc<Mutex<Option<Arc<Mutex<LiveSession>>>>>,
+    /// The terminal size requested via `pty_request`, applied when attaching to a shell
+    pending_size: Arc<Mutex<PtySize>>,
+    /// The workload container to attach to, set by an `exec <name>` request; defaults to
+    /// `CONTAINER_NAME` for a plain shell request
+    target_workload: Arc<Mutex<String>>,
+    /// Active local and remote TCP forwards for this connection
+    forwards: Arc<Mutex<Vec<ActiveForward>>>,
     /// Receiver for server shutdown signals
     shutdown: broadcast::Receiver<()>,
 }
@@ -167,40 +338,13 @@ struct Handler {
 impl russh::server::Handler for Handler {
     type Error = russh::Error;
 
-    /// Opens a new shell session when a client requests one
+    /// Accepts the channel; the shell itself is attached once a PTY size (if any) and a
+    /// shell request have been negotiated
     async fn channel_open_session(
         &mut self,
-        channel: Channel<Msg>,
-        session: &mut Session,
+        _channel: Channel<Msg>,
+        _session: &mut Session,
     ) -> Result<bool, Self::Error> {
-        // Start a new shell process in the Podman container
-        let sock = &format!("unix://{PODMAN_SOCKET_PATH}");
-        let mut child = Command::new("podman")
-            .args(["--url", sock, "exec", "-it", CONTAINER_NAME, "/bin/sh"])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .expect("Failed to start shell");
-
-        // Create stdio streams for the shell process
-        // Unwrap is safe because we set the streams above
-        let stdout = child.stdout.take().unwrap();
-        let stderr = child.stderr.take().unwrap();
-        let channel_id = channel.id();
-        let session = session.handle();
-
-        *self.shell.lock().await = Some(child);
-
-        // Spawn a new task to forward I/O between the shell and SSH channel
-        tokio::spawn(handle_shell_io(
-            stdout,
-            stderr,
-            channel_id,
-            session,
-            self.shutdown.resubscribe(),
-        ));
-
         Ok(true)
     }
 
@@ -218,6 +362,113 @@ impl russh::server::Handler for Handler {
         }
     }
 
+    /// Records the requested terminal size for when the shell is attached. The requested
+    /// terminal name and terminal modes are not recorded or applied to the PTY.
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        _term: &str,
+        col_width: u32,
+        row_height: u32,
+        pix_width: u32,
+        pix_height: u32,
+        _modes: &[(Pty, u32)],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        *self.pending_size.lock().await = PtySize {
+            rows: row_height as u16,
+            cols: col_width as u16,
+            pixel_width: pix_width as u16,
+            pixel_height: pix_height as u16,
+        };
+        session.channel_success(channel);
+        Ok(())
+    }
+
+    /// Treats the requested command as the name of the workload container to attach to,
+    /// then attaches exactly like a plain shell request would. This is how a client picks
+    /// a non-default workload, e.g. `ssh host exec my-other-workload`.
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let requested = String::from_utf8_lossy(data).trim().to_string();
+        if !requested.is_empty() {
+            *self.target_workload.lock().await = requested;
+        }
+        self.shell_request(channel, session).await
+    }
+
+    /// Resizes the active PTY so SIGWINCH propagates into the container
+    async fn window_change_request(
+        &mut self,
+        _channel: ChannelId,
+        col_width: u32,
+        row_height: u32,
+        pix_width: u32,
+        pix_height: u32,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let size = PtySize {
+            rows: row_height as u16,
+            cols: col_width as u16,
+            pixel_width: pix_width as u16,
+            pixel_height: pix_height as u16,
+        };
+        *self.pending_size.lock().await = size;
+        if let Some(live) = &*self.current.lock().await {
+            let _ = live.lock().await.pty.resize(size);
+        }
+        Ok(())
+    }
+
+    /// Attaches to this key's persistent shell in its target workload (the default
+    /// workload unless an `exec <name>` request set a different one), spawning one on a
+    /// PTY sized per the last `pty_request` if none is running yet, and replays its
+    /// scrollback to the new channel
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let workload = self.target_workload.lock().await.clone();
+        let key = (*self.pubkey.as_bytes(), workload.clone());
+        let size = *self.pending_size.lock().await;
+
+        let live = {
+            let mut registry = sessions().lock().await;
+            match registry.get(&key) {
+                Some(live) => live.clone(),
+                None => {
+                    let live = Arc::new(Mutex::new(spawn_live_session(size, &workload)?));
+                    registry.insert(key, live.clone());
+                    live
+                }
+            }
+        };
+
+        let backlog = {
+            let mut live_session = live.lock().await;
+            let _ = live_session.pty.resize(size);
+            *live_session.attached.lock().await = Some(AttachedChannel {
+                channel_id: channel,
+                handle: session.handle(),
+            });
+            live_session.last_active = Instant::now();
+            live_session.scrollback.lock().await.snapshot()
+        };
+
+        if !backlog.is_empty() {
+            let _ = session.data(channel, CryptoVec::from(backlog));
+        }
+
+        *self.current.lock().await = Some(live);
+        session.channel_success(channel);
+        Ok(())
+    }
+
     /// Handles data received from the client
     async fn data(
         &mut self,
@@ -225,35 +476,190 @@ impl russh::server::Handler for Handler {
         data: &[u8],
         _: &mut Session,
     ) -> Result<(), Self::Error> {
-        if let Some(shell) = &mut *self.shell.lock().await {
-            if let Some(stdin) = shell.stdin.as_mut() {
-                stdin.write_all(data).await.ok();
-            }
+        if let Some(live) = &*self.current.lock().await {
+            let mut live_session = live.lock().await;
+            let _ = live_session.writer.write_all(data);
+            live_session.last_active = Instant::now();
         }
         Ok(())
     }
 
-    /// Cleans up when the client closes the channel
+    /// Detaches from the shell (without killing it) when the client closes the channel
     async fn channel_close(&mut self, _: ChannelId, _: &mut Session) -> Result<(), Self::Error> {
-        self.cleanup().await;
+        self.detach().await;
         Ok(())
     }
 
-    /// Cleans up when the client sends EOF
+    /// Detaches from the shell (without killing it) when the client sends EOF
     async fn channel_eof(&mut self, _: ChannelId, _: &mut Session) -> Result<(), Self::Error> {
-        self.cleanup().await;
+        self.detach().await;
         Ok(())
     }
+
+    /// Opens a local-to-remote ("direct-tcpip") tunnel: connects from this service to
+    /// `host_to_connect` (or to that workload container's IP, if it names one) and
+    /// bidirectionally copies bytes with the channel
+    async fn channel_open_direct_tcpip(
+        &mut self,
+        channel: Channel<Msg>,
+        host_to_connect: &str,
+        port_to_connect: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        // If the requested host names one of our workload containers, resolve it to that
+        // container's network IP; otherwise treat it as a regular, directly reachable host
+        let target = match container_ip(host_to_connect).await {
+            Ok(ip) => format!("{ip}:{port_to_connect}"),
+            Err(_) => format!("{host_to_connect}:{port_to_connect}"),
+        };
+
+        let tcp = match TcpStream::connect(&target).await {
+            Ok(tcp) => tcp,
+            // A connection failure here isn't fatal to the session, just this one tunnel
+            Err(_) => return Ok(false),
+        };
+
+        let task = tokio::spawn(async move {
+            let mut tcp = tcp;
+            let mut channel_stream = channel.into_stream();
+            let _ = copy_bidirectional(&mut tcp, &mut channel_stream).await;
+        });
+
+        self.forwards.lock().await.push(ActiveForward {
+            kind: ForwardKind::LocalToRemoteTcp,
+            address: host_to_connect.to_string(),
+            port: port_to_connect,
+            task,
+        });
+
+        Ok(true)
+    }
+
+    /// Opens a remote-to-local ("tcpip-forward") tunnel: listens on `address:port` and, for
+    /// each accepted connection, opens a channel back to the client carrying that traffic
+    async fn tcpip_forward(
+        &mut self,
+        address: &str,
+        port: &mut u32,
+        session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        let listener = match TcpListener::bind((address, *port as u16)).await {
+            Ok(listener) => listener,
+            Err(_) => return Ok(false),
+        };
+
+        // Report back the port we actually bound, in case the client requested port 0
+        *port = listener
+            .local_addr()
+            .map_err(|_| russh::Error::Disconnect)?
+            .port() as u32;
+
+        let session_handle = session.handle();
+        let address = address.to_string();
+        let forward_address = address.clone();
+        let forward_port = *port;
+        let mut shutdown = self.shutdown.resubscribe();
+
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    accepted = listener.accept() => {
+                        let Ok((tcp, origin)) = accepted else { break };
+                        let session_handle = session_handle.clone();
+                        let forward_address = forward_address.clone();
+                        tokio::spawn(async move {
+                            let Ok(channel) = session_handle
+                                .channel_open_forwarded_tcpip(
+                                    &forward_address,
+                                    forward_port,
+                                    &origin.ip().to_string(),
+                                    origin.port() as u32,
+                                )
+                                .await
+                            else {
+                                return;
+                            };
+                            let mut tcp = tcp;
+                            let mut channel_stream = channel.into_stream();
+                            let _ = copy_bidirectional(&mut tcp, &mut channel_stream).await;
+                        });
+                    }
+                    _ = shutdown.recv() => break,
+                }
+            }
+        });
+
+        self.forwards.lock().await.push(ActiveForward {
+            kind: ForwardKind::RemoteToLocalTcp,
+            address,
+            port: forward_port,
+            task,
+        });
+
+        Ok(true)
+    }
+
+    /// Tears down a previously established remote-to-local forward
+    async fn cancel_tcpip_forward(
+        &mut self,
+        address: &str,
+        port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        let mut forwards = self.forwards.lock().await;
+        match forwards.iter().position(|f| {
+            matches!(f.kind, ForwardKind::RemoteToLocalTcp)
+                && f.address == address
+                && f.port == port
+        }) {
+            Some(pos) => {
+                forwards.remove(pos).task.abort();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
 }
 
 impl Handler {
-    /// Cleans up the shell process when a session ends
-    async fn cleanup(&mut self) {
-        if let Some(mut child) = self.shell.lock().await.take() {
-            // Ignore errors when killing the shell process
-            // This is a kill() and we restart the podman container anyway
-            let _ = child.kill().await;
-            let _ = child.wait().await;
+    /// Detaches this connection from its live session (leaving the shell running and
+    /// scheduling its eventual idle-expiry) and tears down any active port forwards
+    async fn detach(&mut self) {
+        if let Some(live) = self.current.lock().await.take() {
+            {
+                let mut live_session = live.lock().await;
+                *live_session.attached.lock().await = None;
+                live_session.last_active = Instant::now();
+            }
+            let workload = self.target_workload.lock().await.clone();
+            schedule_idle_expiry((*self.pubkey.as_bytes(), workload), live);
+        }
+
+        for forward in self.forwards.lock().await.drain(..) {
+            forward.task.abort();
         }
     }
 }
+
+/// Kills and removes `live` from the session registry once it's been detached and idle
+/// for at least `DETACHED_SESSION_TTL`. A reattachment in the meantime cancels this, since
+/// it refreshes `last_active` and re-populates `attached`.
+fn schedule_idle_expiry(key: SessionKey, live: Arc<Mutex<LiveSession>>) {
+    tokio::spawn(async move {
+        tokio::time::sleep(*DETACHED_SESSION_TTL).await;
+
+        let mut registry = sessions().lock().await;
+        let mut live_session = live.lock().await;
+        let still_detached = live_session.attached.lock().await.is_none();
+        let idle_long_enough = live_session.last_active.elapsed() >= *DETACHED_SESSION_TTL;
+
+        if still_detached && idle_long_enough {
+            let _ = live_session.child.kill();
+            let _ = live_session.child.wait();
+            drop(live_session);
+            registry.remove(&key);
+        }
+    });
+}
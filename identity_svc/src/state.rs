@@ -6,49 +6,111 @@
 use crate::{
     encryption::initialize_encryption,
     error::{IdentityError, Result},
+    nonce::NonceStore,
+    signer::{build_signer, Signer},
     ssh::start_ssh_server,
-    storage::{
-        get_operator, get_or_create_instance_key, get_or_create_owner_token, get_owner,
-        get_workload_config, store_operator, store_owner, store_workload_config,
-    },
+    storage::{get_operator, get_owner, get_workload_config, store_operator, store_owner, store_workload_config},
+    store::{build_store, StateStore},
     tdx::{create_tdx_quote, is_tdx_available},
-    workload::run_container,
+    token::UsedJtiStore,
+    workload::CONTAINER_NAME,
+    workloads::WorkloadManager,
 };
-use ed25519_dalek::{SigningKey, VerifyingKey, PUBLIC_KEY_LENGTH};
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
 use lazy_static::lazy_static;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use shared::{
+    cose::{sign_cose, to_cbor},
     encrypted_ppid::get_encrypted_ppid,
+    rate_limit::RateLimiter,
     report_data::create_attestation_hash,
     types::{IdentityInfo, RegisterRequest},
 };
-use std::{env::var, sync::RwLock};
+use std::{env::var, sync::Arc, sync::RwLock, time::Duration};
 
 lazy_static! {
     static ref REGISTRY_URL: String =
         var("REGISTRY_URL").unwrap_or("http://localhost:3000".to_string());
+    /// Wire format used to send `RegisterRequest` to the registry: `json` (default) or `cbor`
+    /// for the COSE_Sign1-wrapped CBOR form
+    static ref REGISTRATION_FORMAT: String =
+        var("REGISTRATION_FORMAT").unwrap_or_else(|_| "json".to_string());
+    /// Base of the cooldown for bad-signature attempts against `/operator/register`: after
+    /// `n` consecutive failures the cooldown is this doubled `n` times
+    static ref SIGNATURE_LIMIT_BASE: Duration = Duration::from_secs(
+        var("SIGNATURE_LIMIT_BASE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1)
+    );
+    /// Base of the cooldown for failed `/operator/register` requests from a given source,
+    /// doubled per consecutive failure the same way
+    static ref REQUEST_LIMIT_BASE: Duration = Duration::from_millis(
+        var("REQUEST_LIMIT_BASE_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(200)
+    );
+    /// The cap both limiters' exponential backoff saturates to
+    static ref RATE_LIMIT_MAX_COOLDOWN: Duration = Duration::from_secs(
+        var("RATE_LIMIT_MAX_COOLDOWN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300)
+    );
 }
 
 /// Axum application state
-#[derive(Debug)]
 pub struct AppState {
     pub workload_config: RwLock<Option<WorkloadConfig>>,
     pub owner: RwLock<Option<IdentityInfo>>,
     pub operator: RwLock<Option<IdentityInfo>>,
-    pub owner_token: String,
-    pub instance_key: SigningKey,
+    pub signer: Arc<dyn Signer>,
     pub instance_pubkey_bytes: [u8; PUBLIC_KEY_LENGTH],
     pub ppid: Vec<u8>,
     pub http_client: Client,
+    pub store: Arc<dyn StateStore>,
+    pub nonces: Arc<NonceStore>,
+    pub workloads: Arc<WorkloadManager>,
+    /// Tracks consumed owner-token `jti`s, so a minted token can't be replayed
+    pub used_jtis: Arc<UsedJtiStore>,
+    /// Keyed on claimed operator pubkey: throttles repeated forged-signature attempts
+    /// against `/operator/register`, which otherwise only checks a signature
+    pub operator_signature_limiter: Arc<RateLimiter>,
+    /// Keyed on source IP: throttles repeated failed `/operator/register` requests from
+    /// a single caller, regardless of which pubkey it claims. Successful requests are not
+    /// counted.
+    pub operator_request_limiter: Arc<RateLimiter>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct WorkloadConfig {
     pub image: String,
+    /// The `sha256:...` digest the image resolved to, verified against the caller's pinned
+    /// digest by `registry_client::verify_image_digest` at configure time
+    pub image_digest: String,
     pub persist_dirs: Vec<String>,
     pub port: u16,
     pub finalized: bool,
+    /// Memory limit in bytes, if constrained
+    #[serde(default)]
+    pub memory: Option<i64>,
+    /// Memory + swap limit in bytes, if constrained
+    #[serde(default)]
+    pub memory_swap: Option<i64>,
+    /// Fractional CPU limit (e.g. `1.5` for one and a half cores), if constrained
+    #[serde(default)]
+    pub cpus: Option<f64>,
+    /// Maximum number of processes/threads the container may create, if constrained
+    #[serde(default)]
+    pub pids_limit: Option<i64>,
+    /// Whether the container's root filesystem is mounted read-only
+    #[serde(default)]
+    pub read_only_rootfs: bool,
+    /// Capabilities re-added on top of the default drop-ALL policy
+    #[serde(default)]
+    pub cap_add: Vec<String>,
 }
 
 /// Error response from the registry
@@ -67,30 +129,48 @@ impl AppState {
     ///
     /// Panics if any required data cannot be read from disk or generated
     pub async fn new() -> Result<Self> {
-        // Get data from disk or generate new data where appropriate
-        let instance_key = get_or_create_instance_key();
-        let instance_pubkey_bytes = instance_key.verifying_key().to_bytes();
-        let owner_token = get_or_create_owner_token();
-        let operator = get_operator()?;
-        let owner = get_owner()?;
-        let workload_config = get_workload_config()?;
+        let store: Arc<dyn StateStore> = Arc::from(build_store());
+
+        // Get data from the store or generate new data where appropriate
+        let signer: Arc<dyn Signer> = Arc::from(build_signer(&*store).await?);
+        let instance_pubkey_bytes = signer.public_key();
+        let operator = get_operator(&*store).await?;
+        let owner = get_owner(&*store).await?;
+        let workload_config = get_workload_config(&*store).await?;
 
         // Get encrypted PPID from TDX, defaulting to instance key if TDX is not available
         let ppid = if is_tdx_available() {
             get_encrypted_ppid().expect("Failed to get encrypted ppid from TDX")
         } else {
-            VerifyingKey::from(&instance_key).to_bytes().to_vec()
+            instance_pubkey_bytes.to_vec()
         };
 
+        let workloads = Arc::new(WorkloadManager::new(store.clone()).await?);
+
         if let Some(owner) = &owner {
             // Mount encrypted storage if owner exists
-            initialize_encryption(&owner.pubkey, &ppid).await;
+            initialize_encryption(&owner.pubkey, &ppid, &*store).await?;
+
+            // Restart every workload this instance was tracking before the restart
+            workloads
+                .restart_persisted()
+                .await
+                .expect("Failed to restart persisted workloads");
 
             if let Some(workload_config) = &workload_config {
-                // Start workload container if workload is configured
-                run_container(workload_config)
+                // The legacy single-workload config predates the workload map; make sure
+                // it's tracked (and so restarted and port-assigned) going forward too
+                let already_tracked = workloads
+                    .list()
                     .await
-                    .expect("Failed to run container");
+                    .iter()
+                    .any(|(name, _)| name == CONTAINER_NAME);
+                if !already_tracked {
+                    workloads
+                        .start(CONTAINER_NAME, workload_config.clone())
+                        .await
+                        .expect("Failed to run container");
+                }
                 if !workload_config.finalized {
                     // Start SSH server if workload is not finalized
                     start_ssh_server(&owner.pubkey).await;
@@ -98,15 +178,38 @@ impl AppState {
             }
         }
 
+        // Issued challenge nonces are replay-resistant, single-use, and short-lived; sweep
+        // out ones that were issued but never consumed
+        let nonces = Arc::new(NonceStore::new());
+        nonces.clone().spawn_sweeper();
+
+        // Consumed owner-token jtis are kept until their token's own exp passes
+        let used_jtis = Arc::new(UsedJtiStore::new());
+        used_jtis.clone().spawn_sweeper();
+
+        // Entries that fail and are never retried would otherwise sit in these maps forever;
+        // sweep out ones whose cooldown has fully elapsed
+        let operator_signature_limiter =
+            Arc::new(RateLimiter::new(*SIGNATURE_LIMIT_BASE, *RATE_LIMIT_MAX_COOLDOWN));
+        operator_signature_limiter.clone().spawn_sweeper();
+        let operator_request_limiter =
+            Arc::new(RateLimiter::new(*REQUEST_LIMIT_BASE, *RATE_LIMIT_MAX_COOLDOWN));
+        operator_request_limiter.clone().spawn_sweeper();
+
         Ok(Self {
-            owner_token,
-            instance_key,
+            signer,
             instance_pubkey_bytes,
             ppid,
             operator: RwLock::new(operator),
             owner: RwLock::new(owner),
             workload_config: RwLock::new(workload_config),
             http_client: Client::new(),
+            store,
+            nonces,
+            workloads,
+            used_jtis,
+            operator_signature_limiter,
+            operator_request_limiter,
         })
     }
 
@@ -128,9 +231,10 @@ impl AppState {
         }
 
         *config_lock = Some(config.clone());
+        drop(config_lock);
 
-        // Persist config to disk
-        store_workload_config(config)?;
+        // Persist config to the store
+        store_workload_config(&*self.store, config).await?;
 
         Ok(())
     }
@@ -138,7 +242,7 @@ impl AppState {
     /// Marks the workload as finalized in state and disk
     /// This does not restart the workload container or stop the SSH server
     /// This will fail if the workload is already finalized
-    pub fn finalize_workload(&self) -> Result<()> {
+    pub async fn finalize_workload(&self) -> Result<()> {
         // Set config in state
         let mut config_lock = self
             .workload_config
@@ -156,18 +260,24 @@ impl AppState {
         }
 
         config.finalized = true;
+        let config = config.clone();
+        drop(config_lock);
+
+        // Persist config to the store
+        store_workload_config(&*self.store, &config).await?;
 
-        // Persist config to disk
-        store_workload_config(config)?;
+        // Bump the seal policy version and re-seal under the current measurement
+        let measurement = crate::sealing::current_measurement().await?;
+        crate::sealing::reseal_for_new_workload(&*self.store, &measurement).await?;
 
         Ok(())
     }
 
-    /// Sets the owner in state and persists it to disk
+    /// Sets the owner in state and persists it to the store
     /// This does not mount encrypted storage or register changes with the registry
-    pub fn set_owner(&self, owner: IdentityInfo) -> Result<()> {
-        // Persist owner to disk
-        store_owner(&owner).map_err(IdentityError::internal)?;
+    pub async fn set_owner(&self, owner: IdentityInfo) -> Result<()> {
+        // Persist owner to the store
+        store_owner(&*self.store, &owner).await?;
 
         // Set owner in state
         let mut owner_lock = self
@@ -179,11 +289,11 @@ impl AppState {
         Ok(())
     }
 
-    /// Sets the operator in state and persists it to disk
+    /// Sets the operator in state and persists it to the store
     /// This does not register changes with the registry
-    pub fn set_operator(&self, operator: IdentityInfo) -> Result<()> {
-        // Persist operator to disk
-        store_operator(&operator).map_err(IdentityError::internal)?;
+    pub async fn set_operator(&self, operator: IdentityInfo) -> Result<()> {
+        // Persist operator to the store
+        store_operator(&*self.store, &operator).await?;
 
         // Set operator in state
         let mut operator_lock = self
@@ -200,6 +310,12 @@ impl AppState {
         let ppid = self.ppid.clone();
         let operator: Option<IdentityInfo> = self.operator.read().unwrap().clone();
         let owner: Option<IdentityInfo> = self.owner.read().unwrap().clone();
+        let image_digest = self
+            .workload_config
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|config| config.image_digest.clone());
 
         // Serialize and hash the state to create the attestation quote report_data
         let report_data = create_attestation_hash(
@@ -207,6 +323,7 @@ impl AppState {
             &ppid,
             operator.as_ref(),
             owner.as_ref(),
+            image_digest.as_deref(),
         );
         // Create attestation quote
         let quote = create_tdx_quote(report_data).await?;
@@ -218,17 +335,35 @@ impl AppState {
             ppid,
             operator,
             owner,
+            image_digest,
+        };
+
+        let request_builder = self.http_client.post(format!("{}/register", *REGISTRY_URL));
+        let request_builder = if *REGISTRATION_FORMAT == "cbor" {
+            let payload = to_cbor(&request).map_err(IdentityError::internal)?;
+            // `sign_cose` needs a synchronous signing closure, but `Signer::sign` is async to
+            // support a remote signing backend; bridge the two by blocking on it here, which
+            // is safe because `main` runs the default multi-threaded `#[tokio::main]` runtime
+            let signer = self.signer.clone();
+            let cose_bytes = tokio::task::block_in_place(|| {
+                sign_cose(payload, |msg| {
+                    tokio::runtime::Handle::current()
+                        .block_on(signer.sign(msg))
+                        .expect("Failed to sign registration payload")
+                        .to_vec()
+                })
+            })
+            .map_err(IdentityError::internal)?;
+            request_builder
+                .header("Content-Type", "application/cbor")
+                .body(cose_bytes)
+        } else {
+            request_builder.json(&request)
         };
 
-        let response = self
-            .http_client
-            .post(format!("{}/register", *REGISTRY_URL))
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| {
-                IdentityError::registry(format!("Failed to connect to registry: {}", e))
-            })?;
+        let response = request_builder.send().await.map_err(|e| {
+            IdentityError::registry(format!("Failed to connect to registry: {}", e))
+        })?;
 
         if !response.status().is_success() {
             // Extract and display error from registry response
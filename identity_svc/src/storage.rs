@@ -1,119 +1,107 @@
-//! Functions for persisting and retrieving data from the filesystem.
+//! Functions for persisting and retrieving data through the configured `StateStore`
 
-use crate::encryption::MOUNT_PATH;
 use crate::error::{IdentityError, Result};
 use crate::state::WorkloadConfig;
+use crate::store::StateStore;
+use crate::workloads::ManagedWorkload;
 use ed25519_dalek::SigningKey;
 use lazy_static::lazy_static;
 use rand::thread_rng;
 use shared::types::IdentityInfo;
+use std::collections::HashMap;
 use std::env::var;
-use std::fs;
-use std::io::ErrorKind;
 use std::path::PathBuf;
 
 lazy_static! {
-    /// The directory where all persistent files can be read from and written to
+    /// The directory the filesystem storage backend reads from and writes to
     pub static ref STORAGE_PATH: PathBuf =
         PathBuf::from(var("STORAGE_PATH").unwrap_or("/mnt".to_string()));
-    /// The path to the workload configuration file
-    pub static ref WORKLOAD_CONFIG_PATH: PathBuf = MOUNT_PATH.join("workload_config.json");
 }
 
+const INSTANCE_KEY: &str = "instance.key";
+const OWNER_KEY: &str = "owner.json";
+const OPERATOR_KEY: &str = "operator.json";
+const WORKLOAD_CONFIG_KEY: &str = "workload_config.json";
+const WORKLOADS_KEY: &str = "workloads.json";
+
 /// Returns the stored workload configuration if it exists
-pub fn get_workload_config() -> Result<Option<WorkloadConfig>> {
-    // Try to read file, panic on errors other than file not found
-    match fs::read_to_string(&*WORKLOAD_CONFIG_PATH) {
-        Ok(data) => Ok(Some(
-            serde_json::from_str::<WorkloadConfig>(&data).expect("Unable to read workload config"),
+pub async fn get_workload_config(store: &dyn StateStore) -> Result<Option<WorkloadConfig>> {
+    match store.get(WORKLOAD_CONFIG_KEY).await? {
+        Some(data) => Ok(Some(
+            serde_json::from_slice(&data).map_err(IdentityError::internal)?,
         )),
-        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
-        Err(err) => panic!("Failed to read workload config: {}", err),
+        None => Ok(None),
     }
 }
 
-/// Stores the workload configuration to disk
-pub fn store_workload_config(config: &WorkloadConfig) -> Result<()> {
-    let data = serde_json::to_string_pretty(config).map_err(IdentityError::internal)?;
-    fs::write(&*WORKLOAD_CONFIG_PATH, data).expect("Unable to write workload config");
-    Ok(())
+/// Stores the workload configuration
+pub async fn store_workload_config(
+    store: &dyn StateStore,
+    config: &WorkloadConfig,
+) -> Result<()> {
+    let data = serde_json::to_vec_pretty(config).map_err(IdentityError::internal)?;
+    store.put(WORKLOAD_CONFIG_KEY, data).await
 }
 
-/// Returns the stored instance key if it exists, or generates a new one
-pub fn get_or_create_instance_key() -> SigningKey {
-    // Create storage directory if it doesn't exist
-    if let Err(err) = fs::create_dir_all(&*STORAGE_PATH) {
-        if err.kind() != ErrorKind::AlreadyExists {
-            panic!("Failed to create storage directory: {}", err);
-        }
+/// Returns the tracked workload map, or an empty one if nothing has been persisted yet
+pub async fn get_workloads(store: &dyn StateStore) -> Result<HashMap<String, ManagedWorkload>> {
+    match store.get(WORKLOADS_KEY).await? {
+        Some(data) => serde_json::from_slice(&data).map_err(IdentityError::internal),
+        None => Ok(HashMap::new()),
     }
+}
 
-    let key_path = STORAGE_PATH.join("instance.key");
-    if key_path.exists() {
-        // Read existing key
-        let key_bytes = fs::read(&key_path).expect("Failed to read instance key");
-        let key_array: &[u8; 32] = &key_bytes.try_into().expect("Failed to parse instance key");
-        SigningKey::from_bytes(key_array)
-    } else {
-        // Generate new key
-        let key = SigningKey::generate(&mut thread_rng());
-        fs::write(&key_path, key.to_bytes()).expect("Failed to write instance key");
-        key
-    }
+/// Stores the tracked workload map
+pub async fn store_workloads(
+    store: &dyn StateStore,
+    workloads: &HashMap<String, ManagedWorkload>,
+) -> Result<()> {
+    let data = serde_json::to_vec_pretty(workloads).map_err(IdentityError::internal)?;
+    store.put(WORKLOADS_KEY, data).await
 }
 
-/// Returns the stored owner token if it exists, or generates a new one
-pub fn get_or_create_owner_token() -> String {
-    let token_path = STORAGE_PATH.join("owner_token.txt");
-    if token_path.exists() {
-        // Read existing owner token
-        fs::read_to_string(&token_path).expect("Failed to read owner token")
+/// Returns the stored instance key if it exists, or generates and persists a new one
+pub async fn get_or_create_instance_key(store: &dyn StateStore) -> Result<SigningKey> {
+    if let Some(key_bytes) = store.get(INSTANCE_KEY).await? {
+        let key_array: [u8; 32] = key_bytes
+            .try_into()
+            .map_err(|_| IdentityError::internal("Failed to parse instance key"))?;
+        Ok(SigningKey::from_bytes(&key_array))
     } else {
-        // Generate new owner token
-        let token = hex::encode(rand::random::<[u8; 32]>());
-        fs::write(&token_path, &token).expect("Failed to generate owner token");
-        token
+        let key = SigningKey::generate(&mut thread_rng());
+        store.put(INSTANCE_KEY, key.to_bytes().to_vec()).await?;
+        Ok(key)
     }
 }
 
 /// Returns the stored owner data if it exists
-pub fn get_owner() -> Result<Option<IdentityInfo>> {
-    let owner_path = STORAGE_PATH.join("owner.json");
-    // Try to read file, panic on errors other than file not found
-    match fs::read_to_string(&owner_path) {
-        Ok(data) => serde_json::from_str(&data)
+pub async fn get_owner(store: &dyn StateStore) -> Result<Option<IdentityInfo>> {
+    match store.get(OWNER_KEY).await? {
+        Some(data) => serde_json::from_slice(&data)
             .map_err(IdentityError::internal)
             .map(Some),
-        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
-        Err(err) => panic!("Failed to read owner file: {}", err),
+        None => Ok(None),
     }
 }
 
-/// Stores the owner data to disk
-pub fn store_owner(owner: &IdentityInfo) -> Result<()> {
-    let owner_path = STORAGE_PATH.join("owner.json");
-    let data = serde_json::to_string_pretty(owner).map_err(IdentityError::internal)?;
-    fs::write(&owner_path, data).expect("Failed to write owner to file");
-    Ok(())
+/// Stores the owner data
+pub async fn store_owner(store: &dyn StateStore, owner: &IdentityInfo) -> Result<()> {
+    let data = serde_json::to_vec_pretty(owner).map_err(IdentityError::internal)?;
+    store.put(OWNER_KEY, data).await
 }
 
 /// Returns the stored operator data if it exists
-pub fn get_operator() -> Result<Option<IdentityInfo>> {
-    let op_path = STORAGE_PATH.join("operator.json");
-    // Try to read file, panic on errors other than file not found
-    match fs::read_to_string(&op_path) {
-        Ok(data) => serde_json::from_str(&data)
+pub async fn get_operator(store: &dyn StateStore) -> Result<Option<IdentityInfo>> {
+    match store.get(OPERATOR_KEY).await? {
+        Some(data) => serde_json::from_slice(&data)
             .map_err(IdentityError::internal)
             .map(Some),
-        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
-        Err(err) => panic!("Failed to read operator file: {}", err),
+        None => Ok(None),
     }
 }
 
-/// Stores the operator data to disk
-pub fn store_operator(operator: &IdentityInfo) -> Result<()> {
-    let op_path = STORAGE_PATH.join("operator.json");
-    let data = serde_json::to_string_pretty(operator).map_err(IdentityError::internal)?;
-    fs::write(&op_path, data).map_err(IdentityError::internal)?;
-    Ok(())
+/// Stores the operator data
+pub async fn store_operator(store: &dyn StateStore, operator: &IdentityInfo) -> Result<()> {
+    let data = serde_json::to_vec_pretty(operator).map_err(IdentityError::internal)?;
+    store.put(OPERATOR_KEY, data).await
 }
@@ -0,0 +1,208 @@
+//! Pluggable storage backends for instance and identity persistence
+//!
+//! All persistent state (the instance key, owner/operator identities, workload
+//! configuration, and seal policy) goes through the `StateStore` trait instead of talking to the
+//! filesystem directly, so a registry deployment can run more than one stateless replica
+//! behind a shared bucket.
+
+use crate::error::{IdentityError, Result};
+use crate::storage::STORAGE_PATH;
+use async_trait::async_trait;
+use std::{env::var, path::PathBuf};
+use tokio::fs;
+
+/// A pluggable key-value storage backend for instance state
+#[async_trait]
+pub trait StateStore: Send + Sync {
+    /// Returns the bytes stored at `key`, or `None` if it doesn't exist
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Writes `bytes` to `key`, overwriting any existing value
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<()>;
+
+    /// Removes the value stored at `key`, if any
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// Lists all keys starting with `prefix`
+    async fn list(&self, prefix: &str) -> Result<Vec<String>>;
+}
+
+/// Builds the storage backend selected by the `STORAGE_BACKEND` env var
+/// Defaults to the filesystem backend rooted at `STORAGE_PATH`
+pub fn build_store() -> Box<dyn StateStore> {
+    match var("STORAGE_BACKEND").as_deref() {
+        Ok("s3") => Box::new(ObjectStore::from_env()),
+        _ => Box::new(FilesystemStore::new(STORAGE_PATH.clone())),
+    }
+}
+
+/// Stores state as files on the local filesystem, rooted at a configurable directory
+///
+/// This is the historical behavior of the identity service
+pub struct FilesystemStore {
+    root: PathBuf,
+}
+
+impl FilesystemStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl StateStore for FilesystemStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.path_for(key)).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(IdentityError::internal(format!(
+                "Failed to read {}: {}",
+                key, err
+            ))),
+        }
+    }
+
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<()> {
+        let path = self.path_for(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await.map_err(|e| {
+                IdentityError::internal(format!("Failed to create {:?}: {}", parent, e))
+            })?;
+        }
+        fs::write(&path, bytes)
+            .await
+            .map_err(|e| IdentityError::internal(format!("Failed to write {}: {}", key, e)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.path_for(key)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(IdentityError::internal(format!(
+                "Failed to delete {}: {}",
+                key, err
+            ))),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut entries = fs::read_dir(&self.root).await.map_err(|e| {
+            IdentityError::internal(format!("Failed to list storage directory: {}", e))
+        })?;
+
+        while let Some(entry) = entries.next_entry().await.map_err(|e| {
+            IdentityError::internal(format!("Failed to read storage entry: {}", e))
+        })? {
+            if let Some(name) = entry.file_name().to_str() {
+                if name.starts_with(prefix) {
+                    keys.push(name.to_string());
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+/// Stores state in an S3-compatible bucket, selected via `STORAGE_BACKEND=s3`
+///
+/// Configured via `S3_BUCKET`, an optional `S3_ENDPOINT`/`S3_PREFIX`, and the standard
+/// `AWS_ACCESS_KEY_ID`/`AWS_SECRET_ACCESS_KEY`/`AWS_REGION` env vars. This lets a registry
+/// run as more than one stateless replica behind a shared bucket.
+pub struct ObjectStore {
+    client: object_store::aws::AmazonS3,
+    prefix: String,
+}
+
+impl ObjectStore {
+    /// Builds an S3-compatible object store from environment variables
+    ///
+    /// # Panics
+    ///
+    /// Panics if `S3_BUCKET` is unset or the client fails to build, since this only runs
+    /// once at startup when `STORAGE_BACKEND=s3` is explicitly requested
+    pub fn from_env() -> Self {
+        let bucket = var("S3_BUCKET").expect("S3_BUCKET must be set when STORAGE_BACKEND=s3");
+        let mut builder = object_store::aws::AmazonS3Builder::from_env().with_bucket_name(bucket);
+        if let Ok(endpoint) = var("S3_ENDPOINT") {
+            builder = builder.with_endpoint(endpoint).with_allow_http(true);
+        }
+        let client = builder
+            .build()
+            .expect("Failed to build S3 object store client");
+        let prefix = var("S3_PREFIX").unwrap_or_default();
+        Self { client, prefix }
+    }
+
+    fn object_path(&self, key: &str) -> object_store::path::Path {
+        object_store::path::Path::from(format!("{}{}", self.prefix, key))
+    }
+}
+
+#[async_trait]
+impl StateStore for ObjectStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        use object_store::ObjectStore as _;
+        match self.client.get(&self.object_path(key)).await {
+            Ok(result) => {
+                let bytes = result.bytes().await.map_err(|e| {
+                    IdentityError::internal(format!("Failed to read {}: {}", key, e))
+                })?;
+                Ok(Some(bytes.to_vec()))
+            }
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(IdentityError::internal(format!(
+                "Failed to get {}: {}",
+                key, err
+            ))),
+        }
+    }
+
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<()> {
+        use object_store::ObjectStore as _;
+        self.client
+            .put(&self.object_path(key), bytes.into())
+            .await
+            .map_err(|e| IdentityError::internal(format!("Failed to put {}: {}", key, e)))?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        use object_store::ObjectStore as _;
+        match self.client.delete(&self.object_path(key)).await {
+            Ok(()) => Ok(()),
+            Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(err) => Err(IdentityError::internal(format!(
+                "Failed to delete {}: {}",
+                key, err
+            ))),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        use futures_util::TryStreamExt;
+        use object_store::ObjectStore as _;
+        // Listings match whole path segments, so list the enclosing directory, filter by
+        // string prefix, and strip `S3_PREFIX` so callers get back plain keys
+        let full_prefix = self.object_path(prefix).to_string();
+        let root = object_store::path::Path::from(self.prefix.clone()).to_string();
+        let dir = full_prefix.rsplit_once('/').map(|(dir, _)| object_store::path::Path::from(dir));
+        let locations = self
+            .client
+            .list(dir.as_ref())
+            .map_ok(|meta| meta.location.to_string())
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(|e| IdentityError::internal(format!("Failed to list {}: {}", prefix, e)))?;
+        Ok(locations
+            .into_iter()
+            .filter(|location| location.starts_with(&full_prefix))
+            .filter_map(|location| Some(location.strip_prefix(&root)?.trim_start_matches('/').to_string()))
+            .collect())
+    }
+}
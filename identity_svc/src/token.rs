@@ -0,0 +1,262 @@
+//! Ed25519-signed `v4.public` PASETO capability tokens.
+//!
+//! Used in place of a shared-secret owner token: `register_operator` mints a short-lived,
+//! single-use token signed by the instance key, so holding `x-token` is a tamper-proof,
+//! time-boxed grant rather than a persisted bearer string that `validate_owner_token` used
+//! to compare verbatim. Only the minimal subset of the PASETO v4.public spec needed for
+//! that is implemented: pre-authentication encoding, Ed25519 signing, and an empty footer.
+
+use crate::{
+    error::{IdentityError, Result},
+    signer::Signer,
+};
+use axum::http::HeaderMap;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env::var,
+    sync::RwLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// PASETO v4.public header, part of both the token string and its signed encoding
+const HEADER: &str = "v4.public.";
+/// `sub` claim on the one-time token `register_operator` mints for `register_owner`
+pub const OWNER_REGISTRATION_SUBJECT: &str = "owner-registration";
+/// `sub` claim on a token minted by `/owner/token`, authorizing one dangerous-capability
+/// change. Distinct from `OWNER_REGISTRATION_SUBJECT` so a capability-grant token can't be
+/// replayed as a registration token, or vice versa.
+pub const CAPABILITY_GRANT_SUBJECT: &str = "capability-grant";
+/// Header key the token is presented in
+const TOKEN_HEADER: &str = "x-token";
+
+lazy_static! {
+    /// How long a minted owner token remains valid before its `exp` claim rejects it
+    static ref OWNER_TOKEN_TTL: Duration = Duration::from_secs(
+        var("OWNER_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600)
+    );
+    /// How often expired, consumed `jti`s are swept out of memory
+    static ref OWNER_TOKEN_SWEEP_INTERVAL: Duration = Duration::from_secs(
+        var("OWNER_TOKEN_SWEEP_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300)
+    );
+}
+
+/// Claims carried in an owner-registration capability token
+#[derive(Debug, Serialize, Deserialize)]
+struct OwnerTokenClaims {
+    iss: String,
+    sub: String,
+    exp: String,
+    jti: String,
+}
+
+/// Tracks consumed `jti`s so a captured token can't be replayed. Entries are kept until
+/// their token's own `exp` passes, since an expired token is already rejected on that basis.
+pub struct UsedJtiStore {
+    used: RwLock<HashMap<String, SystemTime>>,
+}
+
+impl UsedJtiStore {
+    pub fn new() -> Self {
+        Self {
+            used: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Records `jti` (which expires at `exp`) as used. Returns `false` if it was already used.
+    fn consume(&self, jti: &str, exp: SystemTime) -> bool {
+        let mut used = self.used.write().unwrap();
+        if used.contains_key(jti) {
+            return false;
+        }
+        used.insert(jti.to_string(), exp);
+        true
+    }
+
+    /// Drops tracked `jti`s whose token has since expired
+    fn sweep(&self) {
+        let now = SystemTime::now();
+        self.used.write().unwrap().retain(|_, exp| *exp > now);
+    }
+
+    /// Periodically sweeps out expired `jti`s so the map doesn't grow without bound
+    pub fn spawn_sweeper(self: std::sync::Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(*OWNER_TOKEN_SWEEP_INTERVAL).await;
+                self.sweep();
+            }
+        });
+    }
+}
+
+/// Mints a `v4.public` PASETO for `subject`, signed by the instance key and valid for
+/// `OWNER_TOKEN_TTL`
+pub async fn mint_owner_token(
+    signer: &dyn Signer,
+    instance_pubkey_bytes: &[u8; PUBLIC_KEY_LENGTH],
+    subject: &str,
+) -> Result<String> {
+    let claims = OwnerTokenClaims {
+        iss: hex::encode(instance_pubkey_bytes),
+        sub: subject.to_string(),
+        exp: to_rfc3339(SystemTime::now() + *OWNER_TOKEN_TTL),
+        jti: hex::encode(rand::random::<[u8; 16]>()),
+    };
+    let payload = serde_json::to_vec(&claims).map_err(IdentityError::internal)?;
+
+    let signature = signer.sign(&pre_auth_encode(&payload)).await?;
+
+    let mut message = payload;
+    message.extend_from_slice(&signature);
+
+    Ok(format!("{HEADER}{}", URL_SAFE_NO_PAD.encode(message)))
+}
+
+/// Validates the `x-token` header as a `v4.public` PASETO: its signature against
+/// `instance_pubkey_bytes`, that its `sub` matches `expected_subject`, that its `exp`
+/// hasn't passed, and that its `jti` hasn't already been used
+///
+/// # Arguments
+///
+/// * `headers` - The headers of the request
+/// * `instance_pubkey_bytes` - The instance public key the token must be signed by
+/// * `used_jtis` - Store of previously consumed token IDs, to reject replays
+/// * `expected_subject` - The `sub` claim this token must carry, so a token minted for one
+///   purpose can't be replayed for another
+pub fn validate_owner_token(
+    headers: &HeaderMap,
+    instance_pubkey_bytes: &[u8; PUBLIC_KEY_LENGTH],
+    used_jtis: &UsedJtiStore,
+    expected_subject: &str,
+) -> Result<()> {
+    let token = headers
+        .get(TOKEN_HEADER)
+        .ok_or_else(|| IdentityError::unauthorized("Missing token header"))?
+        .to_str()
+        .map_err(|_| IdentityError::unauthorized("Invalid token header"))?;
+
+    let body = token
+        .strip_prefix(HEADER)
+        .ok_or_else(|| IdentityError::unauthorized("Invalid token format"))?;
+    let message = URL_SAFE_NO_PAD
+        .decode(body)
+        .map_err(|_| IdentityError::unauthorized("Invalid token encoding"))?;
+    if message.len() < SIGNATURE_LENGTH {
+        return Err(IdentityError::unauthorized("Invalid token"));
+    }
+    let (payload, sig_bytes) = message.split_at(message.len() - SIGNATURE_LENGTH);
+    let signature = Signature::from_slice(sig_bytes)
+        .map_err(|_| IdentityError::unauthorized("Invalid token signature"))?;
+
+    let verifying_key = VerifyingKey::from_bytes(instance_pubkey_bytes)
+        .expect("Invalid instance public key format");
+    verifying_key
+        .verify_strict(&pre_auth_encode(payload), &signature)
+        .map_err(|_| IdentityError::unauthorized("Invalid token signature"))?;
+
+    let claims: OwnerTokenClaims =
+        serde_json::from_slice(payload).map_err(|_| IdentityError::unauthorized("Invalid token claims"))?;
+
+    if claims.sub != expected_subject {
+        return Err(IdentityError::unauthorized("Token is not valid for this purpose"));
+    }
+
+    let exp = from_rfc3339(&claims.exp)
+        .ok_or_else(|| IdentityError::unauthorized("Invalid token expiry"))?;
+    if SystemTime::now() >= exp {
+        return Err(IdentityError::unauthorized("Token expired"));
+    }
+
+    if !used_jtis.consume(&claims.jti, exp) {
+        return Err(IdentityError::unauthorized("Token already used"));
+    }
+
+    Ok(())
+}
+
+/// PASETO pre-authentication encoding (PAE) of the header, payload, and an empty footer,
+/// which is what's actually signed
+fn pre_auth_encode(payload: &[u8]) -> Vec<u8> {
+    pae(&[HEADER.as_bytes(), payload, &[]])
+}
+
+/// PAE: a length-prefixed concatenation of byte strings, preventing ambiguity between e.g.
+/// `("ab", "c")` and `("a", "bc")`
+fn pae(pieces: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(pieces.len() as u64).to_le_bytes());
+    for piece in pieces {
+        out.extend_from_slice(&(piece.len() as u64).to_le_bytes());
+        out.extend_from_slice(piece);
+    }
+    out
+}
+
+/// Formats a time as a second-precision RFC 3339 UTC timestamp (`2024-01-02T03:04:05Z`).
+/// Hand-rolled since this crate otherwise has no need for a date/time dependency.
+fn to_rfc3339(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let time_of_day = secs % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time_of_day / 3600,
+        (time_of_day / 60) % 60,
+        time_of_day % 60
+    )
+}
+
+/// Parses a timestamp of the form produced by `to_rfc3339`
+fn from_rfc3339(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time_of_day) = s.split_once('T')?;
+
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+
+    let mut time_parts = time_of_day.splitn(3, ':');
+    let hour: u64 = time_parts.next()?.parse().ok()?;
+    let minute: u64 = time_parts.next()?.parse().ok()?;
+    let second: u64 = time_parts.next()?.parse().ok()?;
+
+    let days = days_from_civil(year, month, day);
+    let secs = (days as u64) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Converts a day count since the Unix epoch into a (year, month, day) civil date.
+/// Howard Hinnant's `civil_from_days`: <https://howardhinnant.github.io/date_algorithms.html>
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+/// The inverse of `civil_from_days`: days since the Unix epoch for a civil date
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m as i64 - 3 } else { m as i64 + 9 }) + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
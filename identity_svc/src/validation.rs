@@ -1,29 +1,95 @@
 //! Validation functions for verifying signatures and tokens
+//!
+//! Signed requests are authenticated with an HTTP Message Signature (a simplified form of
+//! RFC 9421): a `Signature-Input` header names which components are covered (the request
+//! method, path, a `Content-Digest` of the body, and a single-use nonce) along with
+//! `created`/`expires`/`alg` parameters, and a `Signature` header carries the signature
+//! value over the canonical string built from those components. Only `alg="ed25519"` is
+//! accepted, and it must cover the full component set above; anything else is rejected.
+//! Clients that don't send a `Signature-Input` header at all fall back to the original
+//! bespoke `x-signature`/`x-nonce` header pair this service used before HTTP Message
+//! Signatures, which always signs `nonce || body` and so is no weaker than the new scheme.
 
 use crate::error::{IdentityError, Result};
-use axum::http::HeaderMap;
+use crate::nonce::NonceStore;
+use axum::http::{HeaderMap, Method};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-/// Header key for owner creation token
-const TOKEN_HEADER: &str = "x-token";
-/// Header key for signature
-const SIGNATURE_HEADER: &str = "x-signature";
+/// Header carrying the signature value: `Signature: sig1=:<base64>:`
+const SIGNATURE_HEADER: &str = "signature";
+/// Header naming the covered components and signature parameters:
+/// `Signature-Input: sig1=("@method" "@path" "content-digest" "x-nonce");created=...;expires=...;alg="ed25519"`
+const SIGNATURE_INPUT_HEADER: &str = "signature-input";
+/// Header the canonical string's `content-digest` component is read from, and whose value
+/// is checked against the actual body
+const CONTENT_DIGEST_HEADER: &str = "content-digest";
+/// Deprecated headers kept for backward compatibility with clients that predate HTTP
+/// Message Signature support
+const LEGACY_SIGNATURE_HEADER: &str = "x-signature";
+const LEGACY_NONCE_HEADER: &str = "x-nonce";
+/// Header carrying the single-use nonce as a covered component of the new scheme
+const NONCE_HEADER: &str = "x-nonce";
+/// Longest span a signature's `created`..`expires` window may cover
+const MAX_SIGNATURE_AGE_SECS: u64 = 300;
 
-/// Validate that the x-signature header matches the request body
+/// Validates a signed request, negotiating between the current HTTP Message Signature
+/// scheme and the deprecated `x-signature`/`x-nonce` header pair. The nonce is consumed
+/// (whether or not the signature is valid) so the request cannot be replayed either way.
 ///
 /// # Arguments
 ///
 /// * `headers` - The headers of the request
-/// * `payload` - The payload of the request
-/// * `identity_pubkey_bytes` - The public key of the owner
+/// * `method` - The request method, covered by the `@method` component
+/// * `path` - The request path, covered by the `@path` component
+/// * `payload` - The request body
+/// * `owner_pubkey_bytes` - The public key of the owner or operator the signature is checked against
+/// * `nonces` - The issued-nonce store to check the request's nonce against
 pub fn validate_signature_header(
     headers: &HeaderMap,
+    method: &Method,
+    path: &str,
     payload: Vec<u8>,
     owner_pubkey_bytes: [u8; 32],
+    nonces: &NonceStore,
 ) -> Result<()> {
-    // Extract signature from headers
+    if headers.contains_key(SIGNATURE_INPUT_HEADER) {
+        validate_http_message_signature(headers, method, path, &payload, owner_pubkey_bytes, nonces)
+    } else {
+        validate_legacy_signature(headers, payload, owner_pubkey_bytes, nonces)
+    }
+}
+
+/// Verifies the deprecated `x-signature` header against `nonce || body`, where `nonce` is a
+/// challenge issued by `POST /challenge`. Kept only for clients that haven't moved to HTTP
+/// Message Signatures.
+fn validate_legacy_signature(
+    headers: &HeaderMap,
+    payload: Vec<u8>,
+    owner_pubkey_bytes: [u8; 32],
+    nonces: &NonceStore,
+) -> Result<()> {
+    let nonce_hex = headers
+        .get(LEGACY_NONCE_HEADER)
+        .ok_or_else(|| IdentityError::unauthorized("Missing nonce header"))?
+        .to_str()
+        .map_err(|_| IdentityError::unauthorized("Invalid nonce header"))?;
+    let nonce: [u8; 32] = hex::decode(nonce_hex)
+        .map_err(|_| IdentityError::unauthorized("Invalid nonce format"))?
+        .try_into()
+        .map_err(|_| IdentityError::unauthorized("Invalid nonce format"))?;
+
+    if !nonces.consume(&nonce) {
+        return Err(IdentityError::unauthorized("Invalid or expired nonce"));
+    }
+
     let sig = headers
-        .get(SIGNATURE_HEADER)
+        .get(LEGACY_SIGNATURE_HEADER)
         .ok_or_else(|| IdentityError::unauthorized("Missing signature header"))?
         .to_str()
         .map_err(|_| IdentityError::unauthorized("Invalid signature header"))?;
@@ -34,33 +100,203 @@ pub fn validate_signature_header(
     let identity_pubkey =
         VerifyingKey::from_bytes(&owner_pubkey_bytes).expect("Invalid identity public key format");
 
-    // Verify signature
+    let mut signed_data = nonce.to_vec();
+    signed_data.extend_from_slice(&payload);
     identity_pubkey
-        .verify_strict(&payload, &sig)
+        .verify_strict(&signed_data, &sig)
         .map_err(|_| IdentityError::unauthorized("Invalid signature"))?;
 
     Ok(())
 }
 
-/// Validate that the x-token header matches the stored owner token
-///
-/// # Arguments
-///
-/// * `headers` - The headers of the request
-/// * `owner_token` - The stored owner token
-pub fn validate_owner_token(headers: &HeaderMap, owner_token: &String) -> Result<()> {
-    // Extract token from headers
-    let token = headers
-        .get(TOKEN_HEADER)
-        .ok_or_else(|| IdentityError::unauthorized("Missing token header"))?
-        .to_str()
-        .map_err(|_| IdentityError::unauthorized("Invalid token header"))
-        .map(String::from)?;
+/// Verifies a `Signature`/`Signature-Input` header pair against the canonical string built
+/// from the components the signer named, and consumes the nonce those components must
+/// cover
+fn validate_http_message_signature(
+    headers: &HeaderMap,
+    method: &Method,
+    path: &str,
+    payload: &[u8],
+    owner_pubkey_bytes: [u8; 32],
+    nonces: &NonceStore,
+) -> Result<()> {
+    let input_header = header_str(headers, SIGNATURE_INPUT_HEADER)?;
+    let (label, components, params) = parse_signature_input(input_header)?;
+
+    let alg = params
+        .get("alg")
+        .ok_or_else(|| IdentityError::unauthorized("Signature-Input is missing alg"))?;
+    match alg.as_str() {
+        "ed25519" => {
+            for required in ["@method", "@path", CONTENT_DIGEST_HEADER, NONCE_HEADER] {
+                if !components.iter().any(|c| c == required) {
+                    return Err(IdentityError::unauthorized(format!(
+                        "Signature must cover {required}"
+                    )));
+                }
+            }
+            check_signature_window(&params)?;
+        }
+        other => {
+            return Err(IdentityError::unauthorized(format!(
+                "Unsupported signature algorithm: {other}"
+            )))
+        }
+    }
+
+    if components.iter().any(|c| c == CONTENT_DIGEST_HEADER) {
+        verify_content_digest(headers, payload)?;
+    }
 
-    // Validate token matches the one from the AppState
-    if &token != owner_token {
-        return Err(IdentityError::unauthorized("Invalid owner token"));
+    // Consume the nonce before verifying the signature, so a replayed request is rejected
+    // even if the signature itself turns out to be invalid
+    let nonce_hex = header_str(headers, NONCE_HEADER)?;
+    let nonce: [u8; 32] = hex::decode(nonce_hex)
+        .map_err(|_| IdentityError::unauthorized("Invalid nonce format"))?
+        .try_into()
+        .map_err(|_| IdentityError::unauthorized("Invalid nonce format"))?;
+    if !nonces.consume(&nonce) {
+        return Err(IdentityError::unauthorized("Invalid or expired nonce"));
     }
 
+    let signing_string = canonical_signing_string(&components, headers, method, path, input_header)?;
+
+    let signature_header = header_str(headers, SIGNATURE_HEADER)?;
+    let sig_bytes = parse_signature_value(signature_header, &label)?;
+    let sig = Signature::from_slice(&sig_bytes)
+        .map_err(|_| IdentityError::unauthorized("Invalid signature"))?;
+    let identity_pubkey =
+        VerifyingKey::from_bytes(&owner_pubkey_bytes).expect("Invalid identity public key format");
+
+    identity_pubkey
+        .verify_strict(signing_string.as_bytes(), &sig)
+        .map_err(|_| IdentityError::unauthorized("Invalid signature"))?;
+
     Ok(())
 }
+
+/// Checks that `created`/`expires` are present, that the window between them isn't
+/// unreasonably long, and that the current time falls inside it
+fn check_signature_window(params: &HashMap<String, String>) -> Result<()> {
+    let created: u64 = params
+        .get("created")
+        .ok_or_else(|| IdentityError::unauthorized("Signature-Input is missing created"))?
+        .parse()
+        .map_err(|_| IdentityError::unauthorized("Invalid created parameter"))?;
+    let expires: u64 = params
+        .get("expires")
+        .ok_or_else(|| IdentityError::unauthorized("Signature-Input is missing expires"))?
+        .parse()
+        .map_err(|_| IdentityError::unauthorized("Invalid expires parameter"))?;
+
+    if expires <= created || expires - created > MAX_SIGNATURE_AGE_SECS {
+        return Err(IdentityError::unauthorized("Invalid signature window"));
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if now < created || now > expires {
+        return Err(IdentityError::unauthorized("Signature has expired"));
+    }
+
+    Ok(())
+}
+
+/// Checks the `Content-Digest` header's `sha-256` value against the actual request body
+fn verify_content_digest(headers: &HeaderMap, payload: &[u8]) -> Result<()> {
+    let digest_header = header_str(headers, CONTENT_DIGEST_HEADER)?;
+    let expected = format!("sha-256=:{}:", STANDARD.encode(Sha256::digest(payload)));
+    if digest_header != expected {
+        return Err(IdentityError::unauthorized(
+            "Content-Digest does not match request body",
+        ));
+    }
+    Ok(())
+}
+
+/// Builds the RFC 9421-style canonical signing string: one line per covered component, in
+/// the order the signer listed them, followed by a final `@signature-params` line carrying
+/// the verbatim component list and parameters from `Signature-Input`
+fn canonical_signing_string(
+    components: &[String],
+    headers: &HeaderMap,
+    method: &Method,
+    path: &str,
+    signature_input_value: &str,
+) -> Result<String> {
+    let mut lines = Vec::with_capacity(components.len() + 1);
+    for component in components {
+        let value = match component.as_str() {
+            "@method" => method.as_str().to_string(),
+            "@path" => path.to_string(),
+            header_name => header_str(headers, header_name)?.to_string(),
+        };
+        lines.push(format!("\"{component}\": {value}"));
+    }
+
+    let params_value = signature_input_value
+        .split_once('=')
+        .map(|(_, rest)| rest)
+        .unwrap_or(signature_input_value);
+    lines.push(format!("\"@signature-params\": {params_value}"));
+
+    Ok(lines.join("\n"))
+}
+
+/// Parses a `Signature-Input` value into its label, ordered component list, and parameters
+///
+/// Expected form: `<label>=("comp1" "comp2");param1=value1;param2="value2"`
+fn parse_signature_input(value: &str) -> Result<(String, Vec<String>, HashMap<String, String>)> {
+    let (label, rest) = value
+        .split_once('=')
+        .ok_or_else(|| IdentityError::unauthorized("Malformed Signature-Input"))?;
+    let rest = rest.trim();
+
+    if !rest.starts_with('(') {
+        return Err(IdentityError::unauthorized("Malformed Signature-Input"));
+    }
+    let close = rest
+        .find(')')
+        .ok_or_else(|| IdentityError::unauthorized("Malformed Signature-Input"))?;
+
+    let components = rest[1..close]
+        .split_whitespace()
+        .map(|s| s.trim_matches('"').to_string())
+        .collect();
+
+    let mut params = HashMap::new();
+    for pair in rest[close + 1..].split(';') {
+        let pair = pair.trim();
+        if pair.is_empty() {
+            continue;
+        }
+        if let Some((key, value)) = pair.split_once('=') {
+            params.insert(key.trim().to_string(), value.trim().trim_matches('"').to_string());
+        }
+    }
+
+    Ok((label.to_string(), components, params))
+}
+
+/// Parses a `Signature` header value for `label` out of its `label=:<base64>:` form
+fn parse_signature_value(value: &str, label: &str) -> Result<Vec<u8>> {
+    let prefix = format!("{label}=:");
+    let encoded = value
+        .strip_prefix(prefix.as_str())
+        .and_then(|rest| rest.strip_suffix(':'))
+        .ok_or_else(|| IdentityError::unauthorized("Malformed Signature header"))?;
+    STANDARD
+        .decode(encoded)
+        .map_err(|_| IdentityError::unauthorized("Invalid signature encoding"))
+}
+
+/// Reads a header's value as `&str`, mapping missing or non-UTF-8 headers to `Unauthorized`
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str> {
+    headers
+        .get(name)
+        .ok_or_else(|| IdentityError::unauthorized(format!("Missing {name} header")))?
+        .to_str()
+        .map_err(|_| IdentityError::unauthorized(format!("Invalid {name} header")))
+}
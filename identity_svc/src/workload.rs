@@ -18,9 +18,24 @@ use std::{
     path::{Component, Path, PathBuf},
 };
 
-/// Name of the Podman container
+/// Name of the workload this instance ran before multi-workload support was added. The
+/// owner-facing `/workload/configure` and `/workload/expose` routes still operate on the
+/// workload of this name, so existing owners don't need to start naming things.
 pub const CONTAINER_NAME: &str = "workload";
 pub const PODMAN_SOCKET_PATH: &str = "/run/podman/podman.sock";
+/// CPU period (in microseconds) that `WorkloadConfig::cpus` is scaled against
+const CPU_PERIOD_MICROS: i64 = 100_000;
+/// Capabilities that grant a path to container breakout or host interference if re-added
+pub const DANGEROUS_CAPABILITIES: &[&str] = &[
+    "SYS_ADMIN",
+    "SYS_MODULE",
+    "SYS_PTRACE",
+    "SYS_RAWIO",
+    "NET_ADMIN",
+    "NET_RAW",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+];
 
 lazy_static! {
     /// Directories in the container are persisted via bind mounts to this folder
@@ -28,9 +43,9 @@ lazy_static! {
     static ref PODMAN: Podman = Podman::unix(PODMAN_SOCKET_PATH);
 }
 
-/// Runs a workload container with the specified configuration
-/// Only exposes the specified port if config.finalized is true
-pub async fn run_container(config: &WorkloadConfig) -> Result<()> {
+/// Runs a named workload container with the specified configuration
+/// Only exposes `config.port` (mapped to `host_port`) if config.finalized is true
+pub async fn run_container(name: &str, config: &WorkloadConfig, host_port: u16) -> Result<()> {
     // Connect to podman
     let podman = Podman::unix(PODMAN_SOCKET_PATH);
 
@@ -40,16 +55,16 @@ pub async fn run_container(config: &WorkloadConfig) -> Result<()> {
     }
 
     // Remove existing container if it exists
-    remove_existing_container().await;
+    remove_existing_container(name).await;
 
     // Create persist directories
-    fs::create_dir_all(&*CONTAINER_PERSIST_DIR).unwrap_or_else(|err| {
+    fs::create_dir_all(container_persist_dir(name)).unwrap_or_else(|err| {
         if err.kind() != ErrorKind::AlreadyExists {
             panic!("Failed to create persist directory: {}", err);
         }
     });
     for dir in &config.persist_dirs {
-        fs::create_dir_all(container_dir_to_host_dir(dir)).unwrap_or_else(|err| {
+        fs::create_dir_all(container_dir_to_host_dir(name, dir)).unwrap_or_else(|err| {
             if err.kind() != ErrorKind::AlreadyExists {
                 panic!("Failed to create persist directory: {}", err);
             }
@@ -62,7 +77,7 @@ pub async fn run_container(config: &WorkloadConfig) -> Result<()> {
         .iter()
         .map(|dir| ContainerMount {
             _type: Some("bind".to_string()),
-            source: Some(container_dir_to_host_dir(dir)),
+            source: Some(container_dir_to_host_dir(name, dir)),
             destination: Some(dir.clone()),
             options: None,
             gid_mappings: None,
@@ -74,7 +89,7 @@ pub async fn run_container(config: &WorkloadConfig) -> Result<()> {
     let port_mappings = if config.finalized {
         vec![PortMapping {
             container_port: Some(config.port),
-            host_port: Some(8080),
+            host_port: Some(host_port),
             protocol: Some("tcp".to_string()),
             host_ip: None,
             range: None,
@@ -91,13 +106,33 @@ pub async fn run_container(config: &WorkloadConfig) -> Result<()> {
         result.map_err(|e| IdentityError::internal(format!("Failed to pull image: {}", e)))?;
     }
 
-    // Create the container
-    let container_config = &ContainerCreateOpts::builder()
+    // Create the container, hardened by default: all capabilities dropped (adding back
+    // only those the config explicitly lists) and whatever cgroup limits are configured
+    let mut builder = ContainerCreateOpts::builder()
         .image(config.image.clone())
-        .name(CONTAINER_NAME)
+        .name(name)
         .mounts(mounts)
         .portmappings(port_mappings)
-        .build();
+        .cap_drop(vec!["ALL".to_string()])
+        .cap_add(config.cap_add.clone())
+        .read_only_rootfs(config.read_only_rootfs);
+
+    if let Some(memory) = config.memory {
+        builder = builder.memory(memory);
+    }
+    if let Some(memory_swap) = config.memory_swap {
+        builder = builder.memory_swap(memory_swap);
+    }
+    if let Some(cpus) = config.cpus {
+        builder = builder
+            .cpu_period(CPU_PERIOD_MICROS)
+            .cpu_quota((cpus * CPU_PERIOD_MICROS as f64) as i64);
+    }
+    if let Some(pids_limit) = config.pids_limit {
+        builder = builder.pids_limit(pids_limit);
+    }
+
+    let container_config = &builder.build();
     let container_id = podman
         .containers()
         .create(container_config)
@@ -116,9 +151,27 @@ pub async fn run_container(config: &WorkloadConfig) -> Result<()> {
     Ok(())
 }
 
-/// Stops and removes the workload container if it exists
-async fn remove_existing_container() {
-    let old_container = PODMAN.containers().get(CONTAINER_NAME);
+/// Returns the named workload container's IP address on the Podman network, so callers
+/// (e.g. SSH port forwarding) can reach services inside its network namespace
+pub async fn container_ip(name: &str) -> Result<std::net::IpAddr> {
+    let inspect = PODMAN
+        .containers()
+        .get(name)
+        .inspect()
+        .await
+        .map_err(|e| IdentityError::internal(format!("Failed to inspect container: {}", e)))?;
+
+    inspect
+        .network_settings
+        .and_then(|settings| settings.ip_address)
+        .filter(|ip| !ip.is_empty())
+        .and_then(|ip| ip.parse().ok())
+        .ok_or_else(|| IdentityError::internal("Container has no IP address"))
+}
+
+/// Stops and removes the named workload container if it exists
+pub async fn remove_existing_container(name: &str) {
+    let old_container = PODMAN.containers().get(name);
     if old_container
         .exists()
         .await
@@ -142,22 +195,36 @@ async fn remove_existing_container() {
     }
 }
 
-/// Maps a container directory to the corresponding host directory
+/// Directory that a named workload's persist directories are bind-mounted from on the host
+fn container_persist_dir(name: &str) -> PathBuf {
+    CONTAINER_PERSIST_DIR.join(name)
+}
+
+/// Maps a named workload's container directory to the corresponding host directory
 ///
 /// # Arguments
 ///
+/// * `name` - The name of the workload the directory belongs to
 /// * `container_dir` - The absolute directory path inside the container
 ///
 /// # Returns
 ///
 /// * The absolute directory path to map to on the host machine
-fn container_dir_to_host_dir(container_dir: &str) -> String {
-    CONTAINER_PERSIST_DIR
+fn container_dir_to_host_dir(name: &str, container_dir: &str) -> String {
+    container_persist_dir(name)
         .join(container_dir.strip_prefix("/").unwrap_or(container_dir))
         .to_string_lossy()
         .to_string()
 }
 
+/// Returns whether `cap_add` re-adds any capability dangerous enough to require the
+/// owner token in addition to the owner's signature
+pub fn contains_dangerous_capability(cap_add: &[String]) -> bool {
+    cap_add
+        .iter()
+        .any(|cap| DANGEROUS_CAPABILITIES.contains(&cap.to_uppercase().as_str()))
+}
+
 /// Sanitizes a container directory path to prevent directory traversal attacks
 fn sanitize_container_dir(dir: &str) -> Result<()> {
     let path = Path::new(dir);
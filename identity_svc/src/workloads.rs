@@ -0,0 +1,109 @@
+//! Tracks every named workload container running on this instance.
+//!
+//! `workload.rs` knows how to run a single named container; this module keeps the map of
+//! which names currently exist, what each one is configured with, and which host port (if
+//! any) its container port is mapped to, so the service can host more than one workload
+//! side by side instead of just the one `workload.rs::CONTAINER_NAME` container. The map is
+//! persisted to the `StateStore` on every change, so `restart_persisted` can bring every
+//! tracked container (and its host port assignment) back after a restart.
+
+use crate::{
+    error::{IdentityError, Result},
+    state::WorkloadConfig,
+    storage::{get_workloads, store_workloads},
+    store::StateStore,
+    workload::{remove_existing_container, run_container},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use tokio::sync::RwLock;
+
+/// Host ports are handed out sequentially starting here, skipping any already assigned to
+/// another tracked workload. A workload keeps the same host port across restarts.
+const BASE_HOST_PORT: u16 = 8080;
+
+/// A workload the manager is currently tracking
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagedWorkload {
+    pub config: WorkloadConfig,
+    pub host_port: u16,
+}
+
+/// Tracks the set of named workload containers running on this instance
+pub struct WorkloadManager {
+    workloads: RwLock<HashMap<String, ManagedWorkload>>,
+    store: Arc<dyn StateStore>,
+}
+
+impl WorkloadManager {
+    /// Loads any workloads this instance was tracking before the process last stopped.
+    /// This only restores the in-memory map; call `restart_persisted` to actually bring
+    /// their containers back up.
+    pub async fn new(store: Arc<dyn StateStore>) -> Result<Self> {
+        let workloads = get_workloads(&*store).await?;
+        Ok(Self {
+            workloads: RwLock::new(workloads),
+            store,
+        })
+    }
+
+    /// Lists all currently tracked workloads by name
+    pub async fn list(&self) -> Vec<(String, ManagedWorkload)> {
+        self.workloads
+            .read()
+            .await
+            .iter()
+            .map(|(name, workload)| (name.clone(), workload.clone()))
+            .collect()
+    }
+
+    /// Restarts every persisted workload's container under its previously assigned host
+    /// port. Meant to be called once at startup, after loading.
+    pub async fn restart_persisted(&self) -> Result<()> {
+        let workloads = self.workloads.read().await;
+        for (name, workload) in workloads.iter() {
+            run_container(name, &workload.config, workload.host_port).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts (or restarts, e.g. after finalizing) the named workload's container,
+    /// allocating it a host port the first time it's seen, and returning that port
+    pub async fn start(&self, name: &str, config: WorkloadConfig) -> Result<u16> {
+        let mut workloads = self.workloads.write().await;
+
+        let host_port = match workloads.get(name) {
+            Some(existing) => existing.host_port,
+            None => allocate_host_port(workloads.values().map(|w| w.host_port)),
+        };
+
+        run_container(name, &config, host_port).await?;
+        workloads.insert(name.to_string(), ManagedWorkload { config, host_port });
+        store_workloads(&*self.store, &workloads).await?;
+
+        Ok(host_port)
+    }
+
+    /// Stops and removes the named workload's container, forgetting it
+    pub async fn stop(&self, name: &str) -> Result<()> {
+        let mut workloads = self.workloads.write().await;
+        if workloads.remove(name).is_none() {
+            return Err(IdentityError::invalid_request("Workload not found"));
+        }
+        store_workloads(&*self.store, &workloads).await?;
+
+        remove_existing_container(name).await;
+        Ok(())
+    }
+}
+
+/// Returns the lowest port at or above `BASE_HOST_PORT` not already in `in_use`
+fn allocate_host_port(in_use: impl Iterator<Item = u16>) -> u16 {
+    let in_use: HashSet<u16> = in_use.collect();
+    (BASE_HOST_PORT..)
+        .find(|port| !in_use.contains(port))
+        .expect("host port space exhausted")
+}
@@ -0,0 +1,53 @@
+//! Offline attestation verification against a configurable PCCS, mirroring the
+//! registry's verification path so an owner can check a quote before trusting it
+
+use dcap_qvl::{collateral::get_collateral, verify::verify};
+use shared::{
+    encrypted_ppid::get_platform_id_from_quote, report_data::reconstruct_attestation_hash,
+    types::RegisterRequest,
+};
+use std::{
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+use tdx_quote::Quote;
+
+/// Reads a JSON-encoded `RegisterRequest` from `path` and verifies its attestation quote
+/// against `pccs_url`, reporting the same attestation checks the registry's
+/// `verify_attestation` does (the operator/owner signature checks are not repeated here)
+pub async fn verify_attestation_file(
+    path: &Path,
+    pccs_url: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read_to_string(path)?;
+    let request: RegisterRequest = serde_json::from_str(&data)?;
+
+    let quote = Quote::from_bytes(&request.attestation_quote)
+        .map_err(|e| format!("Failed to parse quote: {}", e))?;
+
+    let expected_hash = reconstruct_attestation_hash(&request);
+    if quote.report_input_data() != expected_hash {
+        return Err("Attestation hash does not match the quote's report_data".into());
+    }
+    println!("report_data matches the reconstructed attestation hash");
+
+    let quote_ppid = get_platform_id_from_quote(&quote)
+        .map_err(|e| format!("Failed to extract quote ppid: {}", e))?;
+    if quote_ppid != request.ppid {
+        return Err("PPID in the quote does not match the request".into());
+    }
+    println!("PPID matches");
+
+    let collateral = get_collateral(pccs_url, &request.attestation_quote, Duration::from_secs(10))
+        .await
+        .map_err(|e| format!("Failed to get collateral from {}: {:?}", pccs_url, e))?;
+
+    let now = UNIX_EPOCH.elapsed()?.as_secs();
+    let report = verify(&request.attestation_quote, &collateral, now)
+        .map_err(|e| format!("Failed to verify quote: {:?}", e))?;
+
+    println!("Quote verified successfully");
+    println!("{:#?}", report);
+
+    Ok(())
+}
@@ -0,0 +1,26 @@
+//! Hex/base64 encoding helpers matching the crate's `hex_serde`/`base64_serde` formats
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Encoding {
+    Hex,
+    Base64,
+}
+
+impl Encoding {
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Hex => hex::encode(bytes),
+            Self::Base64 => STANDARD.encode(bytes),
+        }
+    }
+
+    pub fn decode(self, text: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self {
+            Self::Hex => Ok(hex::decode(text.trim())?),
+            Self::Base64 => Ok(STANDARD.decode(text.trim())?),
+        }
+    }
+}
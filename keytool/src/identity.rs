@@ -0,0 +1,100 @@
+//! Keypair generation and the `instance_signature`/`identity_signature` operations that
+//! make up a `shared::types::IdentityInfo`
+
+use crate::encoding::Encoding;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use rand::rngs::OsRng;
+use shared::types::IdentityInfo;
+use std::{fs, path::Path};
+
+/// Generates a new ed25519 keypair and writes the pubkey/secret key pair, one per line,
+/// to `out` (or stdout if not given)
+pub fn keygen(out: Option<std::path::PathBuf>, format: Encoding) -> Result<(), Box<dyn std::error::Error>> {
+    let key = SigningKey::generate(&mut OsRng);
+    let output = format!(
+        "pubkey: {}\nsecret: {}\n",
+        format.encode(key.verifying_key().as_bytes()),
+        format.encode(&key.to_bytes()),
+    );
+
+    match out {
+        Some(path) => fs::write(path, output)?,
+        None => print!("{output}"),
+    }
+
+    Ok(())
+}
+
+/// Signs a hex-encoded message with the secret key read from `key_path`
+pub fn sign(key_path: &Path, message_hex: &str, format: Encoding) -> Result<(), Box<dyn std::error::Error>> {
+    let key_bytes = format.decode(&fs::read_to_string(key_path)?)?;
+    let key_array: [u8; 32] = key_bytes
+        .try_into()
+        .map_err(|_| "secret key must be 32 bytes")?;
+    let signing_key = SigningKey::from_bytes(&key_array);
+
+    let message = hex::decode(message_hex)?;
+    let signature = signing_key.sign(&message);
+    println!("{}", format.encode(&signature.to_bytes()));
+
+    Ok(())
+}
+
+/// Verifies a hex-encoded signature against a hex-encoded public key and message
+pub fn verify(pubkey_hex: &str, signature_hex: &str, message_hex: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let pubkey: [u8; PUBLIC_KEY_LENGTH] = hex::decode(pubkey_hex)?
+        .try_into()
+        .map_err(|_| "pubkey must be 32 bytes")?;
+    let signature: [u8; SIGNATURE_LENGTH] = hex::decode(signature_hex)?
+        .try_into()
+        .map_err(|_| "signature must be 64 bytes")?;
+    let message = hex::decode(message_hex)?;
+
+    let verifying_key = VerifyingKey::from_bytes(&pubkey)?;
+    let signature = Signature::from_bytes(&signature);
+
+    match verifying_key.verify_strict(&message, &signature) {
+        Ok(()) => {
+            println!("valid");
+            Ok(())
+        }
+        Err(_) => {
+            println!("invalid");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Builds a full `IdentityInfo` from a pubkey and its two signatures, and prints it as
+/// JSON (or CBOR if `cbor` is set) for submission to the identity service
+pub fn build_identity(
+    pubkey_hex: &str,
+    instance_signature_hex: &str,
+    identity_signature_hex: &str,
+    cbor: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pubkey: [u8; PUBLIC_KEY_LENGTH] = hex::decode(pubkey_hex)?
+        .try_into()
+        .map_err(|_| "pubkey must be 32 bytes")?;
+    let instance_signature: [u8; SIGNATURE_LENGTH] = hex::decode(instance_signature_hex)?
+        .try_into()
+        .map_err(|_| "instance_signature must be 64 bytes")?;
+    let identity_signature: [u8; SIGNATURE_LENGTH] = hex::decode(identity_signature_hex)?
+        .try_into()
+        .map_err(|_| "identity_signature must be 64 bytes")?;
+
+    let identity = IdentityInfo {
+        pubkey,
+        instance_signature,
+        identity_signature,
+    };
+
+    if cbor {
+        use std::io::Write;
+        std::io::stdout().write_all(&shared::cose::to_cbor(&identity)?)?;
+    } else {
+        println!("{}", serde_json::to_string_pretty(&identity)?);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! Offline companion CLI for generating ed25519 keypairs and producing/verifying the
+//! signatures and attestation data used by the identity service and registry, so an
+//! owner or operator can claim an instance from an air-gapped machine without trusting
+//! the instance to build the signatures itself.
+
+mod attest;
+mod encoding;
+mod identity;
+
+use clap::{Parser, Subcommand};
+use encoding::Encoding;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(name = "tdx-keytool", about = "Offline key and attestation tooling for tdx-identity")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a new ed25519 keypair
+    Keygen {
+        /// Where to write the keypair (defaults to stdout)
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Encoding for the printed/written key material
+        #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+        format: Encoding,
+    },
+    /// Sign a message with a secret key (e.g. an instance_signature or identity_signature)
+    Sign {
+        /// Path to the secret key, encoded per --format
+        #[arg(long)]
+        key: PathBuf,
+        /// Hex-encoded message to sign (an instance or identity public key)
+        message: String,
+        #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+        format: Encoding,
+    },
+    /// Verify a signature against a public key
+    Verify {
+        #[arg(long)]
+        pubkey: String,
+        #[arg(long)]
+        signature: String,
+        message: String,
+    },
+    /// Build a full `IdentityInfo` from a pubkey and its two signatures
+    BuildIdentity {
+        #[arg(long)]
+        pubkey: String,
+        #[arg(long)]
+        instance_signature: String,
+        #[arg(long)]
+        identity_signature: String,
+        /// Emit CBOR instead of JSON
+        #[arg(long)]
+        cbor: bool,
+    },
+    /// Verify a TDX attestation quote against a PCCS and a `RegisterRequest`
+    VerifyAttestation {
+        /// Path to a JSON-encoded `RegisterRequest`
+        request: PathBuf,
+        #[arg(long, default_value = "https://localhost:8081/sgx/certification/v4/")]
+        pccs_url: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Keygen { out, format } => identity::keygen(out, format)?,
+        Command::Sign { key, message, format } => identity::sign(&key, &message, format)?,
+        Command::Verify { pubkey, signature, message } => identity::verify(&pubkey, &signature, &message)?,
+        Command::BuildIdentity { pubkey, instance_signature, identity_signature, cbor } => {
+            identity::build_identity(&pubkey, &instance_signature, &identity_signature, cbor)?
+        }
+        Command::VerifyAttestation { request, pccs_url } => {
+            attest::verify_attestation_file(&request, &pccs_url).await?
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+//! Caches DCAP collateral fetched from the PCCS
+//!
+//! A busy registry calling `get_collateral` on every registration hammers the PCCS, and a
+//! brief PCCS outage would otherwise fail every registration. This caches collateral by a
+//! digest of the quote's platform identifier (the same stable per-platform value used to
+//! check the PPID in `verify_attestation`, rather than the quote as a whole, which also
+//! carries a per-registration-unique `report_data`) with a TTL, retries a fetch with
+//! backoff before giving up, and falls back to an on-disk copy so the registry can still
+//! verify quotes during a short PCCS outage.
+
+use dcap_qvl::collateral::get_collateral;
+use dcap_qvl::QuoteCollateralV3;
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use shared::encrypted_ppid::get_platform_id_from_quote;
+use std::{
+    collections::HashMap,
+    env::var,
+    path::PathBuf,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+use tdx_quote::Quote;
+
+lazy_static! {
+    static ref COLLATERAL_CACHE_TTL: Duration = Duration::from_secs(
+        var("COLLATERAL_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600)
+    );
+    static ref COLLATERAL_FETCH_RETRIES: u32 = var("COLLATERAL_FETCH_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(3);
+    static ref COLLATERAL_CACHE_DIR: Option<PathBuf> =
+        var("COLLATERAL_CACHE_DIR").ok().map(PathBuf::from);
+}
+
+struct CachedCollateral {
+    collateral: QuoteCollateralV3,
+    fetched_at: Instant,
+}
+
+/// An in-memory, optionally disk-backed cache of DCAP collateral
+pub struct CollateralCache {
+    entries: RwLock<HashMap<String, CachedCollateral>>,
+}
+
+impl CollateralCache {
+    pub fn new() -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Keys collateral by the quote's platform identifier (shared by every quote from the
+    /// same physical platform) rather than by the whole quote, so instances sharing a
+    /// platform actually share a cache entry instead of each missing on their own nonce
+    fn cache_key(quote_bytes: &[u8]) -> Result<String, String> {
+        let quote =
+            Quote::from_bytes(quote_bytes).map_err(|e| format!("Failed to parse quote: {}", e))?;
+        let platform_id = get_platform_id_from_quote(&quote)
+            .map_err(|e| format!("Failed to extract platform id: {}", e))?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(platform_id);
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    fn disk_path(key: &str) -> Option<PathBuf> {
+        COLLATERAL_CACHE_DIR
+            .as_ref()
+            .map(|dir| dir.join(format!("{key}.json")))
+    }
+
+    fn fresh_from_memory(&self, key: &str) -> Option<QuoteCollateralV3> {
+        self.entries
+            .read()
+            .unwrap()
+            .get(key)
+            .filter(|e| e.fetched_at.elapsed() < *COLLATERAL_CACHE_TTL)
+            .map(|e| e.collateral.clone())
+    }
+
+    fn from_disk(&self, key: &str) -> Option<QuoteCollateralV3> {
+        let data = std::fs::read(Self::disk_path(key)?).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    fn store(&self, key: &str, collateral: &QuoteCollateralV3) {
+        if let Some(path) = Self::disk_path(key) {
+            if let Some(parent) = path.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            if let Ok(data) = serde_json::to_vec(collateral) {
+                let _ = std::fs::write(path, data);
+            }
+        }
+
+        self.entries.write().unwrap().insert(
+            key.to_string(),
+            CachedCollateral {
+                collateral: collateral.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Returns cached collateral for `quote_bytes`, fetching and caching it from
+    /// `pccs_url` (with retry/backoff) if there's no fresh entry. Falls back to a stale
+    /// on-disk copy, if one exists, when every fetch attempt fails.
+    pub async fn get_or_fetch(
+        &self,
+        pccs_url: &str,
+        quote_bytes: &[u8],
+    ) -> Result<QuoteCollateralV3, String> {
+        let key = Self::cache_key(quote_bytes)?;
+
+        if let Some(cached) = self.fresh_from_memory(&key) {
+            return Ok(cached);
+        }
+
+        match Self::fetch_with_retry(pccs_url, quote_bytes).await {
+            Ok(collateral) => {
+                self.store(&key, &collateral);
+                Ok(collateral)
+            }
+            Err(err) => self
+                .from_disk(&key)
+                .ok_or(err)
+                .inspect(|_| eprintln!("Warning: serving stale collateral after a PCCS fetch failure")),
+        }
+    }
+
+    async fn fetch_with_retry(
+        pccs_url: &str,
+        quote_bytes: &[u8],
+    ) -> Result<QuoteCollateralV3, String> {
+        let mut attempt = 0;
+        loop {
+            match get_collateral(pccs_url, quote_bytes, Duration::from_secs(10)).await {
+                Ok(collateral) => return Ok(collateral),
+                Err(err) if attempt < *COLLATERAL_FETCH_RETRIES => {
+                    let backoff = Duration::from_millis(200 * 2u64.pow(attempt));
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(err) => return Err(format!("{:?}", err)),
+            }
+        }
+    }
+}
@@ -22,6 +22,9 @@ pub enum RegistryError {
     #[error("Internal error: {0}")]
     Internal(String),
 
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
+
     #[error("Database error: {0}")]
     Database(#[from] sled::Error),
 }
@@ -43,6 +46,10 @@ impl RegistryError {
     pub fn internal(e: impl ToString) -> Self {
         Self::Internal(e.to_string())
     }
+
+    pub fn too_many_requests(e: impl ToString) -> Self {
+        Self::TooManyRequests(e.to_string())
+    }
 }
 
 /// Response body for error responses
@@ -59,6 +66,7 @@ impl IntoResponse for RegistryError {
             Self::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
             Self::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
             Self::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
+            Self::TooManyRequests(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
             Self::Database(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
         };
 
@@ -2,30 +2,125 @@
 
 use crate::{
     error::{RegistryError, Result},
+    identity_provider::ResolvedIdentity,
     state::{AppState, RegistryEntry},
     tdx::verify_attestation,
+    transparency::{ConsistencyProof, InclusionProof},
     validation::{validate_existing_instance, validate_request},
 };
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{ConnectInfo, Path, State},
+    http::{header::CONTENT_TYPE, HeaderMap},
     Json,
 };
-use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use ed25519_dalek::{VerifyingKey, PUBLIC_KEY_LENGTH};
+use serde::Serialize;
 use shared::{
+    cose::{from_cbor, verify_cose},
     report_data::reconstruct_attestation_hash,
     types::{IdentityInfo, RegisterRequest},
 };
+use std::net::SocketAddr;
 use std::sync::Arc;
 
+/// Content-Type used for the CBOR/COSE_Sign1 wire format
+const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// Parses a `RegisterRequest` from the request body, content-negotiating between the
+/// legacy JSON format and a CBOR payload wrapped in a COSE_Sign1 envelope
+///
+/// For the CBOR form, the envelope is decoded once (untrusted) to discover the instance
+/// pubkey, then the COSE_Sign1 signature is verified against that key before the request
+/// is trusted. This gives a cheap authenticity check ahead of the expensive attestation
+/// verification in `register`.
+fn parse_register_request(headers: &HeaderMap, body: &Bytes) -> Result<RegisterRequest> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/json");
+
+    if content_type == CBOR_CONTENT_TYPE {
+        use coset::CborSerializable;
+        let sign1 = coset::CoseSign1::from_slice(body)
+            .map_err(|e| RegistryError::invalid_request(format!("Invalid COSE_Sign1: {}", e)))?;
+        let payload = sign1
+            .payload
+            .as_ref()
+            .ok_or_else(|| RegistryError::invalid_request("Missing COSE_Sign1 payload"))?;
+        let unverified: RegisterRequest = from_cbor(payload)
+            .map_err(|e| RegistryError::invalid_request(format!("Invalid CBOR payload: {}", e)))?;
+
+        let verifying_key = VerifyingKey::from_bytes(&unverified.instance_pubkey)
+            .map_err(|_| RegistryError::invalid_request("Invalid instance pubkey"))?;
+        verify_cose(body, &verifying_key)
+            .map_err(|_| RegistryError::unauthorized("Invalid COSE_Sign1 envelope signature"))?;
+
+        Ok(unverified)
+    } else {
+        serde_json::from_slice(body).map_err(|_| RegistryError::invalid_request("Invalid payload"))
+    }
+}
+
 /// Main handler for registering an instance
 /// This will validate the request, verify the attestation, and store the entry
 /// in the registry
+///
+/// Failed requests are rate-limited two ways: per source address, so one caller can't
+/// hammer the endpoint regardless of which instance key it claims, and per claimed instance
+/// pubkey, so repeated forgery against one identity is throttled even from rotating
+/// addresses. Both back off exponentially on repeated failure and reset on success.
 pub async fn register(
     State(state): State<Arc<AppState>>,
-    Json(request): Json<RegisterRequest>,
-) -> Result<StatusCode> {
-    validate_request(&request)?;
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<InclusionProof>> {
+    let source_ip = addr.ip().to_string();
+    if !state.request_limiter.check(&source_ip) {
+        return Err(RegistryError::too_many_requests(
+            "Too many failed requests from this address, try again later",
+        ));
+    }
+
+    let result = register_checked(&state, &headers, &body).await;
+    match &result {
+        Ok(_) => state.request_limiter.record_success(&source_ip),
+        Err(_) => state.request_limiter.record_failure(&source_ip),
+    }
+
+    result.map(Json)
+}
+
+/// The registration logic behind `register`, additionally rate-limited per claimed
+/// instance pubkey once the request has been parsed
+async fn register_checked(
+    state: &AppState,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Result<InclusionProof> {
+    let request = parse_register_request(headers, body)?;
+
+    let pubkey_hex = hex::encode(request.instance_pubkey);
+    if !state.signature_limiter.check(&pubkey_hex) {
+        return Err(RegistryError::too_many_requests(
+            "Too many failed attempts for this instance key, try again later",
+        ));
+    }
+
+    let result = register_instance(state, request).await;
+    match &result {
+        Ok(_) => state.signature_limiter.record_success(&pubkey_hex),
+        Err(RegistryError::Unauthorized(_)) => state.signature_limiter.record_failure(&pubkey_hex),
+        Err(_) => {}
+    }
+
+    result
+}
+
+/// Validates the attestation, checks it against any existing entry, and stores it
+async fn register_instance(state: &AppState, request: RegisterRequest) -> Result<InclusionProof> {
+    validate_request(&request, state.identity_provider.as_ref()).await?;
 
     // Create hash and verify attestation
     let hash = reconstruct_attestation_hash(&request);
@@ -39,9 +134,10 @@ pub async fn register(
     // Store entry
     let entry = RegistryEntry {
         ppid: request.ppid,
-        attestation_quote: request.attestation_quote,
+        attestation_quote: request.attestation_quote.clone(),
         operator: request.operator.map(IdentityInfo::from),
         owner: request.owner.map(IdentityInfo::from),
+        image_digest: request.image_digest.clone(),
     };
 
     state
@@ -49,14 +145,51 @@ pub async fn register(
         .await
         .map_err(RegistryError::internal)?;
 
-    Ok(StatusCode::OK)
+    // Append the accepted registration to the transparency log and hand the caller a
+    // signed proof it was included, so they don't have to trust the registry's word for it
+    state
+        .transparency_log
+        .append(state.storage.as_ref(), hash, request.attestation_quote)
+        .await
+}
+
+/// Handler for fetching the inclusion proof of a previously appended log entry
+pub async fn log_inclusion_proof(
+    State(state): State<Arc<AppState>>,
+    Path(index): Path<usize>,
+) -> Result<Json<InclusionProof>> {
+    Ok(Json(state.transparency_log.inclusion_proof(index).await?))
+}
+
+/// Handler for fetching a consistency proof between two historical tree sizes, so an
+/// auditor can verify the log was only ever appended to
+pub async fn log_consistency_proof(
+    State(state): State<Arc<AppState>>,
+    Path((old_size, new_size)): Path<(usize, usize)>,
+) -> Result<Json<ConsistencyProof>> {
+    Ok(Json(
+        state
+            .transparency_log
+            .consistency_proof(old_size, new_size)
+            .await?,
+    ))
+}
+
+/// An instance's registry entry along with any directory identities resolved for its
+/// operator and owner keys
+#[derive(Debug, Serialize)]
+pub struct InstanceResponse {
+    #[serde(flatten)]
+    pub entry: RegistryEntry,
+    pub operator_identity: Option<ResolvedIdentity>,
+    pub owner_identity: Option<ResolvedIdentity>,
 }
 
 /// Handler for getting an instance's registry entry
 pub async fn get_instance(
     State(state): State<Arc<AppState>>,
     Path(pubkey): Path<String>,
-) -> Result<Json<RegistryEntry>> {
+) -> Result<Json<InstanceResponse>> {
     // Decode passed public key as hexidecimal string
     let pubkey_bytes = hex::decode(pubkey)
         .map_err(|_| RegistryError::invalid_request("Invalid hex encoding for public key"))?;
@@ -75,5 +208,19 @@ pub async fn get_instance(
         .await
         .ok_or_else(|| RegistryError::invalid_request("Instance not found"))?;
 
-    Ok(Json(entry))
+    // Resolve human-readable identities for the operator and owner, if configured
+    let operator_identity = match &entry.operator {
+        Some(op) => state.identity_provider.resolve(&op.pubkey).await,
+        None => None,
+    };
+    let owner_identity = match &entry.owner {
+        Some(owner) => state.identity_provider.resolve(&owner.pubkey).await,
+        None => None,
+    };
+
+    Ok(Json(InstanceResponse {
+        entry,
+        operator_identity,
+        owner_identity,
+    }))
 }
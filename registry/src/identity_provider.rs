@@ -0,0 +1,170 @@
+//! Pluggable owner/operator identity providers
+//!
+//! Ties an owner or operator's ed25519 public key to an entry in an organizational
+//! directory, so the registry can enforce an allow-list instead of accepting any
+//! self-asserted key.
+
+use async_trait::async_trait;
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use shared::types::UserType;
+use std::{collections::HashMap, env::var, fs};
+
+/// A directory entry resolved for an owner or operator public key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResolvedIdentity {
+    pub name: String,
+    pub role: String,
+}
+
+/// Resolves owner/operator public keys against an organizational directory
+#[async_trait]
+pub trait IdentityProvider: Send + Sync {
+    /// Returns the human-readable identity tied to `pubkey`, if any
+    async fn resolve(&self, pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> Option<ResolvedIdentity>;
+
+    /// Returns whether `pubkey` is allowed to register as the given `user_type`
+    async fn authorize(&self, user_type: &UserType, pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> bool;
+}
+
+/// Builds the identity provider selected by the `IDENTITY_PROVIDER` env var
+/// Defaults to `NoopProvider`, which authorizes any key and resolves no identity
+pub fn build_identity_provider() -> Box<dyn IdentityProvider> {
+    match var("IDENTITY_PROVIDER").as_deref() {
+        Ok("static") => Box::new(StaticProvider::from_env()),
+        Ok("ldap") => Box::new(LdapProvider::from_env()),
+        _ => Box::new(NoopProvider),
+    }
+}
+
+/// Authorizes any key and resolves no identity, preserving the registry's default
+/// self-asserted-key behavior
+pub struct NoopProvider;
+
+#[async_trait]
+impl IdentityProvider for NoopProvider {
+    async fn resolve(&self, _pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> Option<ResolvedIdentity> {
+        None
+    }
+
+    async fn authorize(&self, _user_type: &UserType, _pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> bool {
+        true
+    }
+}
+
+/// A single entry in the static provider's allow-list config file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StaticEntry {
+    name: String,
+    role: String,
+    /// Which registration roles this key may be used for ("owner", "operator")
+    allowed: Vec<String>,
+}
+
+/// Resolves public keys against a static allow-list config file
+///
+/// The config file is a JSON object mapping hex-encoded public keys to `StaticEntry`
+/// values, selected via `IDENTITY_PROVIDER_CONFIG` (defaults to `identity_providers.json`)
+pub struct StaticProvider {
+    entries: HashMap<String, StaticEntry>,
+}
+
+impl StaticProvider {
+    /// # Panics
+    ///
+    /// Panics if the config file cannot be read or parsed, since this only runs once at
+    /// startup when `IDENTITY_PROVIDER=static` is explicitly requested
+    pub fn from_env() -> Self {
+        let path = var("IDENTITY_PROVIDER_CONFIG")
+            .unwrap_or_else(|_| "identity_providers.json".to_string());
+        let data = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Failed to read identity provider config {}: {}", path, e));
+        let entries = serde_json::from_str(&data)
+            .unwrap_or_else(|e| panic!("Failed to parse identity provider config: {}", e));
+        Self { entries }
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for StaticProvider {
+    async fn resolve(&self, pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> Option<ResolvedIdentity> {
+        self.entries.get(&hex::encode(pubkey)).map(|e| ResolvedIdentity {
+            name: e.name.clone(),
+            role: e.role.clone(),
+        })
+    }
+
+    async fn authorize(&self, user_type: &UserType, pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> bool {
+        self.entries
+            .get(&hex::encode(pubkey))
+            .is_some_and(|e| e.allowed.iter().any(|a| a == &user_type.to_string()))
+    }
+}
+
+lazy_static! {
+    static ref LDAP_URL: String =
+        var("LDAP_URL").unwrap_or_else(|_| "ldap://localhost:389".to_string());
+    static ref LDAP_BASE_DN: String =
+        var("LDAP_BASE_DN").unwrap_or_else(|_| "dc=example,dc=com".to_string());
+    static ref LDAP_BIND_DN: Option<String> = var("LDAP_BIND_DN").ok();
+    static ref LDAP_BIND_PASSWORD: Option<String> = var("LDAP_BIND_PASSWORD").ok();
+    static ref LDAP_PUBKEY_ATTR: String =
+        var("LDAP_PUBKEY_ATTR").unwrap_or_else(|_| "tdxPublicKey".to_string());
+}
+
+/// Resolves public keys by binding to an LDAP directory and searching for the entry
+/// whose `LDAP_PUBKEY_ATTR` attribute matches the hex-encoded public key
+pub struct LdapProvider;
+
+impl LdapProvider {
+    pub fn from_env() -> Self {
+        // Eagerly validate configuration; the connection itself is opened per lookup
+        lazy_static::initialize(&LDAP_URL);
+        lazy_static::initialize(&LDAP_BASE_DN);
+        lazy_static::initialize(&LDAP_PUBKEY_ATTR);
+        Self
+    }
+
+    async fn lookup(&self, pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> Option<(String, String)> {
+        use ldap3::{LdapConnAsync, Scope, SearchEntry};
+
+        let (conn, mut ldap) = LdapConnAsync::new(&LDAP_URL).await.ok()?;
+        ldap3::drive!(conn);
+
+        if let (Some(dn), Some(pw)) = (&*LDAP_BIND_DN, &*LDAP_BIND_PASSWORD) {
+            ldap.simple_bind(dn, pw).await.ok()?.success().ok()?;
+        }
+
+        let filter = format!("({}={})", &*LDAP_PUBKEY_ATTR, hex::encode(pubkey));
+        let (results, _) = ldap
+            .search(&LDAP_BASE_DN, Scope::Subtree, &filter, vec!["cn", "title"])
+            .await
+            .ok()?
+            .success()
+            .ok()?;
+
+        let entry = SearchEntry::construct(results.into_iter().next()?);
+        let name = entry.attrs.get("cn")?.first()?.clone();
+        let role = entry
+            .attrs
+            .get("title")
+            .and_then(|v| v.first())
+            .cloned()
+            .unwrap_or_default();
+
+        Some((name, role))
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for LdapProvider {
+    async fn resolve(&self, pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> Option<ResolvedIdentity> {
+        let (name, role) = self.lookup(pubkey).await?;
+        Some(ResolvedIdentity { name, role })
+    }
+
+    async fn authorize(&self, _user_type: &UserType, pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> bool {
+        self.lookup(pubkey).await.is_some()
+    }
+}
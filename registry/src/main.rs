@@ -3,25 +3,40 @@ use axum::{
     Router,
 };
 use state::AppState;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 
+mod collateral_cache;
 mod error;
 mod handlers;
+mod identity_provider;
 mod state;
+mod storage;
 mod tdx;
+mod transparency;
 mod validation;
 
 #[tokio::main]
 async fn main() {
-    let state = AppState::new().expect("Failed to initialize state");
+    let state = AppState::new().await.expect("Failed to initialize state");
 
     let app = Router::new()
         .route("/register", post(handlers::register))
         .route("/instance/:pubkey", get(handlers::get_instance))
+        .route("/log/proof/:index", get(handlers::log_inclusion_proof))
+        .route(
+            "/log/consistency/:old_size/:new_size",
+            get(handlers::log_consistency_proof),
+        )
         .with_state(Arc::new(state));
 
     println!("Starting server on 0.0.0.0:3000");
     let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -4,13 +4,51 @@
 //! methods for interacting with the database.
 
 use crate::error::{RegistryError, Result};
+use crate::identity_provider::{build_identity_provider, IdentityProvider};
+use crate::storage::{build_storage, Storage};
+use crate::transparency::TransparencyLog;
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
+use shared::rate_limit::RateLimiter;
 use shared::types::{base64_serde, hex_serde, IdentityInfo};
 use std::env::var;
+use std::sync::Arc;
+use std::time::Duration;
+
+lazy_static! {
+    /// Base of the cooldown for bad-signature/bad-attestation attempts against `register`:
+    /// after `n` consecutive failures the cooldown is this doubled `n` times
+    static ref SIGNATURE_LIMIT_BASE: Duration = Duration::from_secs(
+        var("SIGNATURE_LIMIT_BASE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1)
+    );
+    /// Base of the cooldown for failed `register` requests from a given source address,
+    /// doubled per consecutive failure the same way
+    static ref REQUEST_LIMIT_BASE: Duration = Duration::from_millis(
+        var("REQUEST_LIMIT_BASE_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(200)
+    );
+    /// The cap both limiters' exponential backoff saturates to
+    static ref RATE_LIMIT_MAX_COOLDOWN: Duration = Duration::from_secs(
+        var("RATE_LIMIT_MAX_COOLDOWN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300)
+    );
+}
 
-#[derive(Debug)]
 pub struct AppState {
-    pub db: sled::Db,
+    pub storage: Box<dyn Storage>,
+    pub identity_provider: Box<dyn IdentityProvider>,
+    pub transparency_log: TransparencyLog,
+    /// Keyed on instance pubkey: punishes repeated forged attestations against one identity
+    pub signature_limiter: Arc<RateLimiter>,
+    /// Keyed on source IP: punishes one source hammering `register` with many forged identities
+    pub request_limiter: Arc<RateLimiter>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -21,27 +59,43 @@ pub struct RegistryEntry {
     pub attestation_quote: Vec<u8>,
     pub operator: Option<IdentityInfo>,
     pub owner: Option<IdentityInfo>,
+    pub image_digest: Option<String>,
 }
 
 impl AppState {
-    pub fn new() -> Result<Self> {
-        let db_path = var("REGISTRY_DB_PATH").unwrap_or_else(|_| "registry.db".to_string());
-        let db = sled::open(db_path)?;
-        Ok(AppState { db })
+    pub async fn new() -> Result<Self> {
+        let storage = build_storage()?;
+        let identity_provider = build_identity_provider();
+        let transparency_log = TransparencyLog::new(storage.as_ref()).await?;
+
+        // Entries that fail and are never retried would otherwise sit in these maps forever;
+        // sweep out ones whose cooldown has fully elapsed
+        let signature_limiter = Arc::new(RateLimiter::new(*SIGNATURE_LIMIT_BASE, *RATE_LIMIT_MAX_COOLDOWN));
+        signature_limiter.clone().spawn_sweeper();
+        let request_limiter = Arc::new(RateLimiter::new(*REQUEST_LIMIT_BASE, *RATE_LIMIT_MAX_COOLDOWN));
+        request_limiter.clone().spawn_sweeper();
+
+        Ok(AppState {
+            storage,
+            identity_provider,
+            transparency_log,
+            signature_limiter,
+            request_limiter,
+        })
     }
 
     /// Update the registry entry for the given instance public key
     pub async fn insert(&self, key: &[u8], entry: RegistryEntry) -> Result<()> {
         let serialized = bincode::serialize(&entry)
             .map_err(|_| RegistryError::internal("Failed to serialize entry"))?;
-        self.db.insert(key, serialized)?;
-        Ok(())
+        self.storage.insert(key, serialized).await
     }
 
     /// Retrieve the registry entry for the given instance public key
     pub async fn get(&self, key: &[u8]) -> Option<RegistryEntry> {
-        self.db
+        self.storage
             .get(key)
+            .await
             .ok()?
             .and_then(|v| bincode::deserialize(&v).ok())
     }
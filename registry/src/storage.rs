@@ -0,0 +1,291 @@
+//! Pluggable storage backends for registry entries, with an optional at-rest encryption layer
+//!
+//! `AppState` used to talk to `sled` directly, which ties the registry to a single local
+//! database file. The `Storage` trait abstracts the byte-level key/value operations it
+//! actually needs so a deployment can swap in an S3-compatible bucket (or an in-memory
+//! backend for tests) without touching `AppState::insert`/`get`. `EncryptedStorage` wraps
+//! any backend and compresses+seals each value before it reaches the backend, so entries
+//! are never stored in plaintext once `REGISTRY_ENCRYPTION_KEY` is set.
+
+use crate::error::{RegistryError, Result};
+use async_trait::async_trait;
+use crypto_secretbox::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Nonce, XSalsa20Poly1305,
+};
+use std::{collections::HashMap, env::var, sync::RwLock};
+
+/// A pluggable byte-level key/value storage backend for registry entries
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Returns the bytes stored at `key`, or `None` if it doesn't exist
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Writes `value` to `key`, overwriting any existing value
+    async fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<()>;
+
+    /// Returns every stored key/value pair
+    async fn scan(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+}
+
+/// Builds the storage backend selected by the `STORAGE_BACKEND` env var, wrapped in the
+/// encrypted-blob codec if `REGISTRY_ENCRYPTION_KEY` is set
+/// Defaults to the existing `sled`-backed behavior
+pub fn build_storage() -> Result<Box<dyn Storage>> {
+    let backend: Box<dyn Storage> = match var("STORAGE_BACKEND").as_deref() {
+        Ok("memory") => Box::new(MemoryStorage::new()),
+        Ok("s3") => Box::new(ObjectStorage::from_env()),
+        _ => Box::new(SledStorage::from_env()?),
+    };
+
+    match var("REGISTRY_ENCRYPTION_KEY").ok() {
+        Some(key_hex) => Ok(Box::new(EncryptedStorage::new(backend, &key_hex)?)),
+        None => Ok(backend),
+    }
+}
+
+/// Stores entries in a local `sled` database, selected by `REGISTRY_DB_PATH`
+///
+/// This is the historical behavior of the registry
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+impl SledStorage {
+    pub fn from_env() -> Result<Self> {
+        let db_path = var("REGISTRY_DB_PATH").unwrap_or_else(|_| "registry.db".to_string());
+        Ok(Self {
+            db: sled::open(db_path)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for SledStorage {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key)?.map(|v| v.to_vec()))
+    }
+
+    async fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.db.insert(key, value)?;
+        Ok(())
+    }
+
+    async fn scan(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.db
+            .iter()
+            .map(|entry| entry.map(|(k, v)| (k.to_vec(), v.to_vec())))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(RegistryError::from)
+    }
+}
+
+/// Stores entries in memory, losing them on restart. Intended for tests.
+pub struct MemoryStorage {
+    entries: RwLock<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .entries
+            .read()
+            .expect("Failed to acquire read lock on memory storage")
+            .get(key)
+            .cloned())
+    }
+
+    async fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.entries
+            .write()
+            .expect("Failed to acquire write lock on memory storage")
+            .insert(key.to_vec(), value);
+        Ok(())
+    }
+
+    async fn scan(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .entries
+            .read()
+            .expect("Failed to acquire read lock on memory storage")
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+}
+
+/// Stores entries in an S3-compatible bucket, selected via `STORAGE_BACKEND=s3`
+///
+/// Configured via `S3_BUCKET`, an optional `S3_ENDPOINT`/`S3_PREFIX`, and the standard
+/// `AWS_ACCESS_KEY_ID`/`AWS_SECRET_ACCESS_KEY`/`AWS_REGION` env vars, so the registry can
+/// run as more than one stateless replica behind a shared bucket.
+pub struct ObjectStorage {
+    client: object_store::aws::AmazonS3,
+    prefix: String,
+}
+
+impl ObjectStorage {
+    /// # Panics
+    ///
+    /// Panics if `S3_BUCKET` is unset or the client fails to build, since this only runs
+    /// once at startup when `STORAGE_BACKEND=s3` is explicitly requested
+    pub fn from_env() -> Self {
+        let bucket = var("S3_BUCKET").expect("S3_BUCKET must be set when STORAGE_BACKEND=s3");
+        let mut builder = object_store::aws::AmazonS3Builder::from_env().with_bucket_name(bucket);
+        if let Ok(endpoint) = var("S3_ENDPOINT") {
+            builder = builder.with_endpoint(endpoint).with_allow_http(true);
+        }
+        let client = builder
+            .build()
+            .expect("Failed to build S3 object store client");
+        let prefix = var("S3_PREFIX").unwrap_or_default();
+        Self { client, prefix }
+    }
+
+    fn object_path(&self, key: &[u8]) -> object_store::path::Path {
+        object_store::path::Path::from(format!("{}{}", self.prefix, hex::encode(key)))
+    }
+}
+
+#[async_trait]
+impl Storage for ObjectStorage {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        use object_store::ObjectStore;
+        match self.client.get(&self.object_path(key)).await {
+            Ok(result) => {
+                let bytes = result
+                    .bytes()
+                    .await
+                    .map_err(|e| RegistryError::internal(format!("Failed to read entry: {}", e)))?;
+                Ok(Some(bytes.to_vec()))
+            }
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(RegistryError::internal(format!(
+                "Failed to get entry: {}",
+                err
+            ))),
+        }
+    }
+
+    async fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        use object_store::ObjectStore;
+        self.client
+            .put(&self.object_path(key), value.into())
+            .await
+            .map_err(|e| RegistryError::internal(format!("Failed to put entry: {}", e)))?;
+        Ok(())
+    }
+
+    async fn scan(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        use futures_util::TryStreamExt;
+        use object_store::ObjectStore;
+        let prefix = object_store::path::Path::from(self.prefix.clone());
+        let metas = self
+            .client
+            .list(Some(&prefix))
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(|e| RegistryError::internal(format!("Failed to list entries: {}", e)))?;
+
+        let mut entries = Vec::with_capacity(metas.len());
+        for meta in metas {
+            let filename = meta.location.filename().unwrap_or_default();
+            let key = hex::decode(filename)
+                .map_err(|_| RegistryError::internal("Invalid entry key encoding"))?;
+            if let Some(value) = self.get(&key).await? {
+                entries.push((key, value));
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Wraps any `Storage` backend, compressing each value with zstd and sealing it with
+/// XSalsa20-Poly1305 (NaCl's `secretbox` construction) under a fixed key before it's
+/// written, storing only a random nonce followed by the ciphertext. This protects registry
+/// contents at rest without requiring the wrapped backend to know anything about encryption.
+pub struct EncryptedStorage {
+    inner: Box<dyn Storage>,
+    cipher: XSalsa20Poly1305,
+}
+
+impl EncryptedStorage {
+    /// `key_hex` must be a 64-character hex string (32 raw bytes)
+    pub fn new(inner: Box<dyn Storage>, key_hex: &str) -> Result<Self> {
+        let key_bytes = hex::decode(key_hex)
+            .map_err(|_| RegistryError::internal("REGISTRY_ENCRYPTION_KEY must be hex-encoded"))?;
+        if key_bytes.len() != 32 {
+            return Err(RegistryError::internal(
+                "REGISTRY_ENCRYPTION_KEY must decode to 32 bytes",
+            ));
+        }
+        let cipher = XSalsa20Poly1305::new_from_slice(&key_bytes)
+            .map_err(|_| RegistryError::internal("Invalid REGISTRY_ENCRYPTION_KEY"))?;
+        Ok(Self { inner, cipher })
+    }
+
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let compressed = zstd::encode_all(plaintext, 0)
+            .map_err(|e| RegistryError::internal(format!("Failed to compress entry: {}", e)))?;
+
+        let nonce = XSalsa20Poly1305::generate_nonce(&mut OsRng);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, compressed.as_slice())
+            .map_err(|_| RegistryError::internal("Failed to seal entry"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < 24 {
+            return Err(RegistryError::internal("Sealed entry is too short"));
+        }
+        let (nonce_bytes, ciphertext) = sealed.split_at(24);
+        let nonce = Nonce::from_slice(nonce_bytes);
+
+        let compressed = self
+            .cipher
+            .decrypt(nonce, ciphertext)
+            .map_err(|_| RegistryError::internal("Failed to open sealed entry"))?;
+
+        zstd::decode_all(compressed.as_slice())
+            .map_err(|e| RegistryError::internal(format!("Failed to decompress entry: {}", e)))
+    }
+}
+
+#[async_trait]
+impl Storage for EncryptedStorage {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.inner.get(key).await? {
+            Some(sealed) => Ok(Some(self.open(&sealed)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let sealed = self.seal(&value)?;
+        self.inner.insert(key, sealed).await
+    }
+
+    async fn scan(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner
+            .scan()
+            .await?
+            .into_iter()
+            .map(|(key, sealed)| self.open(&sealed).map(|value| (key, value)))
+            .collect()
+    }
+}
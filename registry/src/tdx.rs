@@ -1,13 +1,13 @@
 //! Intel TDX attestation verification functions
 
-use dcap_qvl::collateral::get_collateral;
 use dcap_qvl::verify::{verify, VerifiedReport};
 use lazy_static::lazy_static;
-use shared::encrypted_ppid::get_encrypted_ppid_from_quote;
+use shared::encrypted_ppid::get_platform_id_from_quote;
 use std::env::var;
-use std::time::{Duration, UNIX_EPOCH};
+use std::time::UNIX_EPOCH;
 use tdx_quote::Quote;
 
+use crate::collateral_cache::CollateralCache;
 use crate::error::{RegistryError, Result};
 
 lazy_static! {
@@ -17,6 +17,8 @@ lazy_static! {
     /// PCCS URL for getting collateral from Intel
     static ref PCCS_URL: String =
         var("PCCS_URL").unwrap_or("https://localhost:8081/sgx/certification/v4/".to_string());
+    /// Cache of collateral fetched from the PCCS, so repeated quotes don't re-fetch it
+    static ref COLLATERAL_CACHE: CollateralCache = CollateralCache::new();
 }
 
 /// Verifies a quote is legitimate, attests to the given hash, and has the correct PPID
@@ -47,18 +49,20 @@ pub async fn verify_attestation(
         return Ok(None);
     }
 
-    // Extract the PPID from the quote and verify it matches the expected PPID
-    let quote_ppid = get_encrypted_ppid_from_quote(&quote_obj)
+    // Extract the platform id from the quote (the encrypted PPID for cert types 2/3, or a
+    // digest of the PCK leaf cert for the production cert type 5) and verify it matches
+    let quote_ppid = get_platform_id_from_quote(&quote_obj)
         .map_err(|e| RegistryError::unauthorized(format!("Failed to extract quote ppid: {}", e)))?;
 
     if quote_ppid != ppid {
         return Err(RegistryError::unauthorized("PPID mismatch"));
     };
 
-    // Get collateral from Intel and use it to verify the quote
-    let collateral = get_collateral(&PCCS_URL, quote, Duration::from_secs(10))
+    // Get collateral from the cache, or from Intel on a cache miss, and use it to verify the quote
+    let collateral = COLLATERAL_CACHE
+        .get_or_fetch(&PCCS_URL, quote)
         .await
-        .map_err(|e| RegistryError::unauthorized(format!("Failed to get collateral: {:?}", e)))?;
+        .map_err(|e| RegistryError::unauthorized(format!("Failed to get collateral: {}", e)))?;
 
     let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
     verify(quote, &collateral, now)
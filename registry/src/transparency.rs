@@ -0,0 +1,285 @@
+//! Append-only transparency log for accepted registrations, with RFC 6962-style Merkle
+//! inclusion and consistency proofs
+//!
+//! Every accepted `RegisterRequest` appends a leaf recording its reconstructed attestation
+//! hash, quote, and timestamp. Leaves are cached in memory but persisted to `Storage` and
+//! reloaded on startup, so a restart doesn't reuse leaf indices or reset the tree out from
+//! under proofs issued before it. The tree root is recomputed from the in-memory leaves on
+//! each access, so an auditor who watches `GET /log/proof/:index` over time can check that a
+//! given registration was included, and `GET /log/consistency/:old/:new` lets them check the
+//! log was only ever appended to, without trusting the registry's word for either.
+
+use crate::{
+    error::{RegistryError, Result},
+    storage::Storage,
+};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+/// Storage key the log's signing key is persisted under
+const LOG_SIGNING_KEY: &[u8] = b"transparency_log.signing_key";
+
+/// Storage key the number of appended leaves is persisted under
+const LOG_COUNT_KEY: &[u8] = b"transparency_log.count";
+
+/// Storage key a given leaf is persisted under
+fn leaf_key(index: usize) -> Vec<u8> {
+    format!("transparency_log.leaf.{index}").into_bytes()
+}
+
+/// One appended leaf: the attestation hash and quote from an accepted registration, and
+/// when it was appended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LogEntry {
+    attestation_hash: Vec<u8>,
+    quote: Vec<u8>,
+    timestamp: u64,
+}
+
+/// A signed proof that `leaf_index` is included in the tree of size `tree_size` with the
+/// given `root`
+#[derive(Debug, Serialize)]
+pub struct InclusionProof {
+    pub leaf_index: usize,
+    pub tree_size: usize,
+    pub root: String,
+    pub audit_path: Vec<String>,
+    pub signature: String,
+}
+
+/// A proof that the tree at `new_size` is an append-only extension of the tree at `old_size`
+#[derive(Debug, Serialize)]
+pub struct ConsistencyProof {
+    pub old_size: usize,
+    pub new_size: usize,
+    pub proof: Vec<String>,
+}
+
+/// An append-only Merkle log of accepted registrations, cached in memory and persisted to
+/// `Storage`
+pub struct TransparencyLog {
+    entries: RwLock<Vec<LogEntry>>,
+    signing_key: SigningKey,
+}
+
+impl TransparencyLog {
+    /// Loads (or generates and persists) the log's signing key from `storage`, and reloads
+    /// every previously appended leaf so leaf indices and proofs survive a restart
+    pub async fn new(storage: &dyn Storage) -> Result<Self> {
+        let signing_key = match storage.get(LOG_SIGNING_KEY).await? {
+            Some(bytes) => {
+                let key_bytes: [u8; 32] = bytes
+                    .try_into()
+                    .map_err(|_| RegistryError::internal("Stored log signing key is malformed"))?;
+                SigningKey::from_bytes(&key_bytes)
+            }
+            None => {
+                let key = SigningKey::generate(&mut OsRng);
+                storage
+                    .insert(LOG_SIGNING_KEY, key.to_bytes().to_vec())
+                    .await?;
+                key
+            }
+        };
+
+        let count = match storage.get(LOG_COUNT_KEY).await? {
+            Some(bytes) => {
+                let count_bytes: [u8; 8] = bytes
+                    .try_into()
+                    .map_err(|_| RegistryError::internal("Stored log count is malformed"))?;
+                u64::from_le_bytes(count_bytes) as usize
+            }
+            None => 0,
+        };
+
+        let mut entries = Vec::with_capacity(count);
+        for index in 0..count {
+            let raw = storage
+                .get(&leaf_key(index))
+                .await?
+                .ok_or_else(|| RegistryError::internal("Missing transparency log leaf"))?;
+            let entry: LogEntry = bincode::deserialize(&raw)
+                .map_err(|_| RegistryError::internal("Corrupt transparency log leaf"))?;
+            entries.push(entry);
+        }
+
+        Ok(Self {
+            entries: RwLock::new(entries),
+            signing_key,
+        })
+    }
+
+    /// The public key auditors can use to verify signed roots
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    /// Appends a new leaf for an accepted registration, persists it to `storage`, and
+    /// returns its inclusion proof
+    pub async fn append(
+        &self,
+        storage: &dyn Storage,
+        attestation_hash: [u8; 64],
+        quote: Vec<u8>,
+    ) -> Result<InclusionProof> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut entries = self.entries.write().await;
+        let leaf_index = entries.len();
+        let entry = LogEntry {
+            attestation_hash: attestation_hash.to_vec(),
+            quote,
+            timestamp,
+        };
+
+        let serialized =
+            bincode::serialize(&entry).map_err(|_| RegistryError::internal("Failed to serialize leaf"))?;
+        storage.insert(&leaf_key(leaf_index), serialized).await?;
+        storage
+            .insert(LOG_COUNT_KEY, ((leaf_index + 1) as u64).to_le_bytes().to_vec())
+            .await?;
+
+        entries.push(entry);
+
+        Ok(self
+            .proof_for(&entries, leaf_index)
+            .expect("leaf was just appended"))
+    }
+
+    /// Returns the inclusion proof for a previously appended leaf
+    pub async fn inclusion_proof(&self, leaf_index: usize) -> Result<InclusionProof> {
+        let entries = self.entries.read().await;
+        self.proof_for(&entries, leaf_index)
+            .ok_or_else(|| RegistryError::invalid_request("Leaf index out of range"))
+    }
+
+    fn proof_for(&self, entries: &[LogEntry], leaf_index: usize) -> Option<InclusionProof> {
+        if leaf_index >= entries.len() {
+            return None;
+        }
+
+        let leaves: Vec<[u8; 32]> = entries.iter().map(leaf_hash).collect();
+        let root = merkle_root(&leaves);
+        let audit_path = audit_path(&leaves, leaf_index);
+
+        Some(InclusionProof {
+            leaf_index,
+            tree_size: leaves.len(),
+            root: hex::encode(root),
+            audit_path: audit_path.into_iter().map(hex::encode).collect(),
+            signature: hex::encode(self.signing_key.sign(&root).to_bytes()),
+        })
+    }
+
+    /// Returns a consistency proof between two historical tree sizes
+    pub async fn consistency_proof(&self, old_size: usize, new_size: usize) -> Result<ConsistencyProof> {
+        let entries = self.entries.read().await;
+        if old_size == 0 || old_size > new_size || new_size > entries.len() {
+            return Err(RegistryError::invalid_request(
+                "old_size and new_size must satisfy 0 < old_size <= new_size <= tree_size",
+            ));
+        }
+
+        let leaves: Vec<[u8; 32]> = entries[..new_size].iter().map(leaf_hash).collect();
+        let proof = consistency_proof_hashes(&leaves, old_size);
+
+        Ok(ConsistencyProof {
+            old_size,
+            new_size,
+            proof: proof.into_iter().map(hex::encode).collect(),
+        })
+    }
+}
+
+/// RFC 6962 leaf hash: `SHA-256(0x00 || entry_bytes)`
+fn leaf_hash(entry: &LogEntry) -> [u8; 32] {
+    let entry_bytes = bincode::serialize(entry).expect("LogEntry is always serializable");
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(&entry_bytes);
+    hasher.finalize().into()
+}
+
+/// RFC 6962 interior node hash: `SHA-256(0x01 || left || right)`
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The largest power of two strictly less than `n` (`n` must be at least 2)
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+/// Computes the Merkle tree hash of `leaves`. A single-leaf tree's root is that leaf's own
+/// hash; otherwise the left subtree is the largest power of two strictly less than the leaf
+/// count, per RFC 6962.
+fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    match leaves.len() {
+        0 => Sha256::new().finalize().into(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&merkle_root(&leaves[..k]), &merkle_root(&leaves[k..]))
+        }
+    }
+}
+
+/// The audit path for `leaf_index`: the sibling hash at every level from leaf to root
+fn audit_path(leaves: &[[u8; 32]], leaf_index: usize) -> Vec<[u8; 32]> {
+    if leaves.len() <= 1 {
+        return Vec::new();
+    }
+    let k = split_point(leaves.len());
+    if leaf_index < k {
+        let mut path = audit_path(&leaves[..k], leaf_index);
+        path.push(merkle_root(&leaves[k..]));
+        path
+    } else {
+        let mut path = audit_path(&leaves[k..], leaf_index - k);
+        path.push(merkle_root(&leaves[..k]));
+        path
+    }
+}
+
+/// RFC 6962 `PROOF(m, D[n])`: the consistency proof hashes between an old tree of size `m`
+/// and the tree formed by `leaves`
+fn consistency_proof_hashes(leaves: &[[u8; 32]], m: usize) -> Vec<[u8; 32]> {
+    fn subproof(leaves: &[[u8; 32]], m: usize, complete: bool) -> Vec<[u8; 32]> {
+        let n = leaves.len();
+        if m == n {
+            if complete {
+                Vec::new()
+            } else {
+                vec![merkle_root(leaves)]
+            }
+        } else {
+            let k = split_point(n);
+            if m <= k {
+                let mut proof = subproof(&leaves[..k], m, complete);
+                proof.push(merkle_root(&leaves[k..]));
+                proof
+            } else {
+                let mut proof = subproof(&leaves[k..], m - k, false);
+                proof.push(merkle_root(&leaves[..k]));
+                proof
+            }
+        }
+    }
+
+    subproof(leaves, m, true)
+}
@@ -2,6 +2,7 @@
 
 use crate::{
     error::{RegistryError, Result},
+    identity_provider::IdentityProvider,
     state::RegistryEntry,
 };
 use ed25519_dalek::PUBLIC_KEY_LENGTH;
@@ -47,11 +48,20 @@ fn verify_identity_signatures(
 
 /// Validate a register request
 /// This will check that the operator and owner have signed the instance public key
-/// with the correct keys. It will also check that the owner is only set if the operator is set
-pub fn validate_request(request: &RegisterRequest) -> Result<()> {
+/// with the correct keys. It will also check that the owner is only set if the operator is set,
+/// and that the identity provider authorizes each key for the role it is registering as
+pub async fn validate_request(
+    request: &RegisterRequest,
+    identity_provider: &dyn IdentityProvider,
+) -> Result<()> {
     // Validate operator if present
     if let Some(op) = &request.operator {
         verify_identity_signatures(&request.instance_pubkey, op, UserType::Operator)?;
+        if !identity_provider.authorize(&UserType::Operator, &op.pubkey).await {
+            return Err(RegistryError::unauthorized(
+                "Operator key is not authorized by the identity provider",
+            ));
+        }
     }
 
     // Validate owner if present
@@ -62,6 +72,11 @@ pub fn validate_request(request: &RegisterRequest) -> Result<()> {
             ));
         }
         verify_identity_signatures(&request.instance_pubkey, owner, UserType::Owner)?;
+        if !identity_provider.authorize(&UserType::Owner, &owner.pubkey).await {
+            return Err(RegistryError::unauthorized(
+                "Owner key is not authorized by the identity provider",
+            ));
+        }
     }
 
     Ok(())
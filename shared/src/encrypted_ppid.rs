@@ -1,49 +1,104 @@
-//! Provides functions to extract the encrypted PPID
-//! It supports getting the PPID either from a quote or direcrtly from TDX
+//! Provides functions to extract the encrypted PPID, or the PCK certificate chain, from a
+//! TDX quote's QE report certification data. It supports getting the PPID either from a
+//! quote or directly from TDX.
 
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use configfs_tsm::create_tdx_quote;
+use sha2::{Digest, Sha256};
 use tdx_quote::{CertificationData, QeReportCertificationData, Quote};
 
+const PEM_CERT_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_CERT_END: &str = "-----END CERTIFICATE-----";
+
 /// Errors that can occur when extracting the encrypted PPID
 #[derive(Debug, thiserror::Error)]
 pub enum PpidError {
     #[error("The PPID is not encrypted")]
     NotEncryptedPpid,
+    #[error("The certification data is not a PCK certificate chain")]
+    NotPckChain,
     #[error("Invalid data length")]
     InvalidDataLength,
     #[error("Invalid report data")]
     InvalidReportData,
+    #[error("Invalid PEM certificate chain")]
+    InvalidPemCertificate,
     #[error("Quote parse error: {0}")]
     QuoteParseError(tdx_quote::QuoteParseError),
     #[error("Quote generation error: {0}")]
     QuoteGenerationError(configfs_tsm::QuoteGenerationError),
 }
 
+/// The certification data carried in a TDX quote's QE report, which is either an
+/// RSA-OAEP encrypted PPID (the provisioning-time cert types) or a PCK certificate chain
+/// (the production cert type), alongside the PCE's ID in both cases
+#[derive(Debug)]
+pub enum CertData {
+    /// RSA-2048/3072-OAEP encrypted PPID (cert type 2 or 3)
+    EncryptedPpid { bytes: Vec<u8>, pceid: i16 },
+    /// A PCK certificate chain (cert type 5), leaf first, as raw DER blobs
+    PckChain { certs: Vec<Vec<u8>>, pceid: i16 },
+}
+
 /// Extracts the encrypted PPID from a quote
 pub fn get_encrypted_ppid_from_quote(quote: &Quote) -> Result<Vec<u8>, PpidError> {
-    // The quotes we get from TDX should always be attesting to some report_data
-    if let CertificationData::QeReportCertificationData(cert_data) = &quote.certification_data {
-        extract_encrypted_ppid(cert_data)
-    } else {
-        Err(PpidError::InvalidReportData)
+    match extract_cert_data(quote)? {
+        CertData::EncryptedPpid { bytes, .. } => Ok(bytes),
+        CertData::PckChain { .. } => Err(PpidError::NotEncryptedPpid),
     }
 }
 
-/// Gets the encrypted PPID from directly from TDX
+/// Extracts the PCK certificate chain from a quote, leaf certificate first, as DER blobs
+pub fn get_pck_chain_from_quote(quote: &Quote) -> Result<Vec<Vec<u8>>, PpidError> {
+    match extract_cert_data(quote)? {
+        CertData::PckChain { certs, .. } => Ok(certs),
+        CertData::EncryptedPpid { .. } => Err(PpidError::NotPckChain),
+    }
+}
+
+/// Returns a stable per-platform identifier for a quote, regardless of which certification
+/// data type it carries: the RSA-OAEP encrypted PPID for the provisioning cert types (2/3),
+/// or the SHA-256 of the PCK leaf certificate for the production PEM chain cert type (5).
+///
+/// For cert type 5 the PCK leaf certificate (not some separately encoded PPID) is the value
+/// actually bound to the platform, and its full chain of custody up to the Intel root is
+/// validated separately by the DCAP quote verification itself (`dcap_qvl::verify`), so this
+/// only needs to return a stable digest of it, not re-verify the chain.
+pub fn get_platform_id_from_quote(quote: &Quote) -> Result<Vec<u8>, PpidError> {
+    match extract_cert_data(quote)? {
+        CertData::EncryptedPpid { bytes, .. } => Ok(bytes),
+        CertData::PckChain { certs, .. } => {
+            let leaf = certs.first().ok_or(PpidError::InvalidPemCertificate)?;
+            Ok(Sha256::digest(leaf).to_vec())
+        }
+    }
+}
+
+/// Gets this platform's identifier directly from TDX
 pub fn get_encrypted_ppid() -> Result<Vec<u8>, PpidError> {
-    // Just generate a quote with empty report_data and extract the PPID from it
+    // Just generate a quote with empty report_data and extract the platform id from it
     let quote_raw = create_tdx_quote([0u8; 64]).map_err(PpidError::QuoteGenerationError)?;
     let quote = Quote::from_bytes(&quote_raw).map_err(PpidError::QuoteParseError)?;
-    get_encrypted_ppid_from_quote(&quote)
+    get_platform_id_from_quote(&quote)
 }
 
-/// Manually extracts the encrypted PPID from TDX quote certification data
-fn extract_encrypted_ppid(cert_data: &QeReportCertificationData) -> Result<Vec<u8>, PpidError> {
+/// Extracts the structured certification data (encrypted PPID or PCK chain) from a quote
+fn extract_cert_data(quote: &Quote) -> Result<CertData, PpidError> {
+    // The quotes we get from TDX should always be attesting to some report_data
+    if let CertificationData::QeReportCertificationData(cert_data) = &quote.certification_data {
+        parse_cert_data(cert_data)
+    } else {
+        Err(PpidError::InvalidReportData)
+    }
+}
+
+/// Manually parses the QE report's certification data into a `CertData`
+fn parse_cert_data(cert_data: &QeReportCertificationData) -> Result<CertData, PpidError> {
     if cert_data.certification_data.len() < 6 {
         return Err(PpidError::InvalidDataLength);
     }
 
-    // This is 3 during testing, but it should be 5
     let cert_type = i16::from_le_bytes([
         cert_data.certification_data[0],
         cert_data.certification_data[1],
@@ -52,23 +107,53 @@ fn extract_encrypted_ppid(cert_data: &QeReportCertificationData) -> Result<Vec<u
     // Strip type and size prefixes
     let data = &cert_data.certification_data[6..];
 
-    // This is kept in the code for debugging purposes
     // The last two bytes of the cert_data are the pceid
     let pceid = i16::from_le_bytes([
         cert_data.certification_data[cert_data.certification_data.len() - 2],
         cert_data.certification_data[cert_data.certification_data.len() - 1],
     ]);
-    eprint!("PCEID: ");
-    dbg!(pceid);
 
-    // Extract the PPID based on the cert type
-    let ppid = match cert_type {
-        2 => Ok(data[..256].to_vec()), // RSA-2048-OAEP
-        3 => Ok(data[..384].to_vec()), // RSA-3072-OAEP
+    match cert_type {
+        2 => Ok(CertData::EncryptedPpid {
+            bytes: data[..256].to_vec(), // RSA-2048-OAEP
+            pceid,
+        }),
+        3 => Ok(CertData::EncryptedPpid {
+            bytes: data[..384].to_vec(), // RSA-3072-OAEP
+            pceid,
+        }),
+        5 => Ok(CertData::PckChain {
+            certs: parse_pem_chain(data)?,
+            pceid,
+        }),
         _ => Err(PpidError::NotEncryptedPpid),
-    }?;
-    eprint!("PPID: ");
-    dbg!(hex::encode(&ppid));
+    }
+}
+
+/// Splits concatenated PEM certificates into DER blobs, in the order they appear
+fn parse_pem_chain(data: &[u8]) -> Result<Vec<Vec<u8>>, PpidError> {
+    let text = std::str::from_utf8(data).map_err(|_| PpidError::InvalidPemCertificate)?;
+
+    let mut certs = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(PEM_CERT_BEGIN) {
+        let body = &rest[start + PEM_CERT_BEGIN.len()..];
+        let end = body
+            .find(PEM_CERT_END)
+            .ok_or(PpidError::InvalidPemCertificate)?;
+
+        let base64_body: String = body[..end].chars().filter(|c| !c.is_whitespace()).collect();
+        let der = STANDARD
+            .decode(base64_body)
+            .map_err(|_| PpidError::InvalidPemCertificate)?;
+        certs.push(der);
+
+        rest = &body[end + PEM_CERT_END.len()..];
+    }
+
+    if certs.is_empty() {
+        return Err(PpidError::InvalidPemCertificate);
+    }
 
-    Ok(ppid)
+    Ok(certs)
 }
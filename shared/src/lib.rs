@@ -0,0 +1,8 @@
+//! Types and helpers shared between the registry and the identity service
+
+pub mod cose;
+pub mod encrypted_ppid;
+pub mod rate_limit;
+pub mod report_data;
+pub mod sig_validation;
+pub mod types;
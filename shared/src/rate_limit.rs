@@ -0,0 +1,130 @@
+//! Per-key rate limiting with exponential backoff on repeated failures
+//!
+//! Each tracked key (an instance pubkey, a source IP, ...) remembers only the time of its
+//! last failure and how many failures it's racked up in a row. A request for that key is
+//! allowed once `base * 2^failures` (capped at `max_cooldown`) has elapsed since the last
+//! failure; a success clears the count. This turns a forged-signature or forged-attestation
+//! retry loop into an exponentially slower one instead of a free-for-all, without requiring
+//! any state beyond what's needed to answer "has this key failed recently, and how often".
+
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    env::var,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    /// How often the background sweep clears out entries that have fallen out of cooldown
+    /// and never succeeded
+    static ref RATE_LIMIT_SWEEP_INTERVAL: Duration = Duration::from_secs(
+        var("RATE_LIMIT_SWEEP_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60)
+    );
+}
+
+/// The last time a key failed, and how many times it's failed in a row since its last success
+struct RateLimitState {
+    last_failure: Instant,
+    failure_count: u32,
+}
+
+/// A `RwLock`-protected map of per-key failure backoff state
+///
+/// Services typically keep two of these side by side with different keys and `base`/
+/// `max_cooldown` tuning: one keyed on an identity's pubkey to punish repeated forgery
+/// against that identity, and one keyed on the caller's source IP to punish a single
+/// source hammering the endpoint with many different forged identities.
+pub struct RateLimiter {
+    state: RwLock<HashMap<String, RateLimitState>>,
+    base: Duration,
+    max_cooldown: Duration,
+}
+
+impl RateLimiter {
+    /// Creates a limiter whose cooldown after `n` consecutive failures is
+    /// `min(base * 2^n, max_cooldown)`
+    pub fn new(base: Duration, max_cooldown: Duration) -> Self {
+        Self {
+            state: RwLock::new(HashMap::new()),
+            base,
+            max_cooldown,
+        }
+    }
+
+    /// Returns whether `key` is currently allowed to make a request, i.e. it has no
+    /// recorded failure or its cooldown since the last one has already elapsed
+    pub fn check(&self, key: &str) -> bool {
+        let state = self
+            .state
+            .read()
+            .expect("Failed to acquire read lock on rate limiter state");
+
+        match state.get(key) {
+            Some(entry) => {
+                entry.last_failure.elapsed() >= cooldown(self.base, self.max_cooldown, entry.failure_count)
+            }
+            None => true,
+        }
+    }
+
+    /// Records a failed attempt for `key`, growing its cooldown for next time
+    pub fn record_failure(&self, key: &str) {
+        let mut state = self
+            .state
+            .write()
+            .expect("Failed to acquire write lock on rate limiter state");
+
+        state
+            .entry(key.to_string())
+            .and_modify(|entry| entry.failure_count = entry.failure_count.saturating_add(1))
+            .or_insert(RateLimitState {
+                last_failure: Instant::now(),
+                failure_count: 1,
+            })
+            .last_failure = Instant::now();
+    }
+
+    /// Clears `key`'s failure count on success, so it doesn't keep paying for past failures
+    pub fn record_success(&self, key: &str) {
+        self.state
+            .write()
+            .expect("Failed to acquire write lock on rate limiter state")
+            .remove(key);
+    }
+
+    /// Drops every entry whose cooldown has fully elapsed. `record_success` already clears
+    /// entries that eventually succeed; this is for keys that fail and are never retried,
+    /// which would otherwise sit in the map forever.
+    pub fn sweep(&self) {
+        let base = self.base;
+        let max_cooldown = self.max_cooldown;
+        self.state
+            .write()
+            .expect("Failed to acquire write lock on rate limiter state")
+            .retain(|_, entry| {
+                entry.last_failure.elapsed() < cooldown(base, max_cooldown, entry.failure_count)
+            });
+    }
+
+    /// Spawns a background task that periodically sweeps out entries past their cooldown
+    pub fn spawn_sweeper(self: Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(*RATE_LIMIT_SWEEP_INTERVAL).await;
+                self.sweep();
+            }
+        });
+    }
+}
+
+/// The cooldown after `failure_count` consecutive failures: `base * 2^failure_count`,
+/// saturating to `max_cooldown` rather than overflowing for very large counts
+fn cooldown(base: Duration, max_cooldown: Duration, failure_count: u32) -> Duration {
+    1u32.checked_shl(failure_count)
+        .and_then(|multiplier| base.checked_mul(multiplier))
+        .map_or(max_cooldown, |cooldown| cooldown.min(max_cooldown))
+}
@@ -25,11 +25,14 @@ pub fn reconstruct_attestation_hash(request: &RegisterRequest) -> [u8; 64] {
         &request.ppid,
         request.operator.as_ref(),
         request.owner.as_ref(),
+        request.image_digest.as_deref(),
     )
 }
 
-/// Creates a hash of the instance public key, PPID, and optional operator and owner
-/// information. This hash is used to create the attestation quote.
+/// Creates a hash of the instance public key, PPID, optional operator and owner
+/// information, and the pinned digest of the workload image (if one is configured). This
+/// hash is used to create the attestation quote, so a quote commits not just to who
+/// controls the instance but to the exact image bytes it's running.
 ///
 /// # Returns
 ///
@@ -39,6 +42,7 @@ pub fn create_attestation_hash(
     ppid: &[u8],
     operator: Option<&IdentityInfo>,
     owner: Option<&IdentityInfo>,
+    image_digest: Option<&str>,
 ) -> [u8; 64] {
     let mut hasher = Sha512::new();
 
@@ -60,5 +64,10 @@ pub fn create_attestation_hash(
         hasher.update(owner.identity_signature);
     }
 
+    // Add the workload image digest if one has been pinned
+    if let Some(digest) = image_digest {
+        hasher.update(digest.as_bytes());
+    }
+
     hasher.finalize().into()
 }
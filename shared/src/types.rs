@@ -16,6 +16,11 @@ pub struct RegisterRequest {
     pub attestation_quote: Vec<u8>,
     pub operator: Option<IdentityInfo>,
     pub owner: Option<IdentityInfo>,
+    /// The `sha256:...` digest of the workload image pinned via `/workload/configure`, if
+    /// one has been configured. Folded into the attestation hash so the quote commits to
+    /// the exact image bytes the workload runs.
+    #[serde(default)]
+    pub image_digest: Option<String>,
 }
 
 /// Allows for more descriptive error messages in generic validation functions